@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 use bevy_egui::{egui, EguiContexts, EguiPlugin};
-use bevy_fsc_point_cloud::{PointCloudAsset, PointCloudPlaybackControls, PotreePointCloud};
+use bevy_fsc_point_cloud::{PlaybackControls, PointCloudAsset, PotreePointCloud};
 use smooth_bevy_cameras::{
     controllers::fps::{FpsCameraBundle, FpsCameraController, FpsCameraPlugin},
     LookTransformPlugin,
@@ -42,7 +42,9 @@ fn startup(mut commands: Commands, asset_server: Res<AssetServer>) {
         .spawn(PotreePointCloud {
             mesh: point_cloud.clone(),
             point_size: 1.0,
+            ..default()
         })
+        .insert(PlaybackControls::default())
         .insert(SpatialBundle {
             transform: Transform::from_rotation(Quat::from_rotation_x(
                 -std::f32::consts::FRAC_PI_2,
@@ -56,10 +58,12 @@ fn startup(mut commands: Commands, asset_server: Res<AssetServer>) {
 fn controls_window(
     mut ctx: EguiContexts,
     pc: Res<PointCloud>,
-    mut controls: ResMut<PointCloudPlaybackControls>,
+    mut point_clouds: Query<&mut PlaybackControls>,
     assets: Res<Assets<PointCloudAsset>>,
 ) {
-    let controls = controls.controls_mut(&pc.0);
+    let Ok(mut controls) = point_clouds.get_single_mut() else {
+        return;
+    };
     let Some(asset) = assets.get(&pc.0) else {
         return;
     };
@@ -76,5 +80,17 @@ fn controls_window(
 
         ui.add(egui::Slider::new(&mut controls.speed, -1.0..=10.0).text("Speed"));
         ui.add(egui::Slider::new(&mut controls.time, 0.0..=animation_duration).text("Seek"));
+
+        ui.horizontal(|ui| {
+            if ui.button("Previous frame").clicked() {
+                controls.previous_frame(asset);
+            }
+            if ui.button("Next frame").clicked() {
+                controls.next_frame(asset);
+            }
+            if let Some(frame) = controls.frame_index(asset) {
+                ui.label(format!("Frame {} / {}", frame + 1, asset.frame_count()));
+            }
+        });
     });
 }
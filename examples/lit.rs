@@ -0,0 +1,81 @@
+use bevy::{prelude::*, render::render_resource::PrimitiveTopology};
+use bevy_fsc_point_cloud::{
+    PointCloudAsset, PointCloudShading, PointCloudShadowCaster, PointShape, PotreePointCloud,
+    ATTRIBUTE_COLOR,
+};
+
+const GRID_SIZE: usize = 200;
+
+fn main() {
+    App::new()
+        .add_plugins((
+            DefaultPlugins.set(WindowPlugin::default()),
+            bevy_fsc_point_cloud::PointCloudPlugin,
+        ))
+        .add_systems(Startup, startup)
+        .run();
+}
+
+fn startup(
+    mut commands: Commands,
+    mut assets: ResMut<Assets<PointCloudAsset>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    commands.spawn(Camera3dBundle {
+        transform: Transform::from_xyz(0.0, 40.0, 70.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ..default()
+    });
+    commands.spawn(DirectionalLightBundle {
+        directional_light: DirectionalLight {
+            shadows_enabled: true,
+            ..default()
+        },
+        transform: Transform::from_xyz(1.0, 2.0, 0.5).looking_at(Vec3::ZERO, Vec3::Y),
+        ..default()
+    });
+    commands.spawn(PointLightBundle {
+        point_light: PointLight {
+            intensity: 20000.0,
+            range: 40.0,
+            color: Color::ORANGE,
+            ..default()
+        },
+        transform: Transform::from_xyz(10.0, 8.0, 10.0),
+        ..default()
+    });
+
+    // Rolling hills, whose shape only shows once they are lit.
+    let positions: Vec<[f32; 3]> = (0..GRID_SIZE * GRID_SIZE)
+        .map(|i| {
+            let (x, z) = ((i % GRID_SIZE) as f32 / 4.0, (i / GRID_SIZE) as f32 / 4.0);
+            let (x, z) = (x - GRID_SIZE as f32 / 8.0, z - GRID_SIZE as f32 / 8.0);
+            [x, 3.0 * (x * 0.3).sin() * (z * 0.2).cos(), z]
+        })
+        .collect();
+    let colors = vec![[0.4f32, 0.6, 0.3]; positions.len()];
+    let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(ATTRIBUTE_COLOR, colors);
+    let mut point_cloud = PointCloudAsset::new(mesh);
+    point_cloud.estimate_normals(8);
+
+    commands
+        .spawn(PotreePointCloud {
+            mesh: assets.add(point_cloud),
+            point_size: 0.4,
+            ..default()
+        })
+        .insert(PointCloudShading::Lit)
+        .insert(PointShape::OrientedDisk)
+        .insert(PointCloudShadowCaster)
+        .insert(SpatialBundle::default());
+
+    // A lit mesh for comparison, casting its shadow on the point cloud.
+    commands.spawn(PbrBundle {
+        mesh: meshes.add(shape::UVSphere::default().into()),
+        material: materials.add(Color::WHITE.into()),
+        transform: Transform::from_xyz(0.0, 8.0, 0.0).with_scale(Vec3::splat(4.0)),
+        ..default()
+    });
+}
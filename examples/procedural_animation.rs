@@ -0,0 +1,69 @@
+use bevy::{prelude::*, render::render_resource::PrimitiveTopology};
+use bevy_fsc_point_cloud::{
+    AnimationFrame, AnimationUpload, FramePoints, PlaybackControls, PointCloudAnimation,
+    PointCloudAsset, PotreePointCloud, ScalarColorRamp,
+};
+
+const GRID_SIZE: usize = 100;
+const NUM_FRAMES: usize = 60;
+
+fn main() {
+    App::new()
+        .add_plugins((
+            DefaultPlugins.set(WindowPlugin::default()),
+            bevy_fsc_point_cloud::PointCloudPlugin,
+        ))
+        .add_systems(Startup, startup)
+        .run();
+}
+
+fn startup(mut commands: Commands, mut assets: ResMut<Assets<PointCloudAsset>>) {
+    commands.spawn(Camera3dBundle {
+        transform: Transform::from_xyz(0.0, 60.0, 120.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ..default()
+    });
+
+    let positions: Vec<Vec3> = (0..GRID_SIZE * GRID_SIZE)
+        .map(|i| {
+            let (x, z) = ((i % GRID_SIZE) as f32, (i / GRID_SIZE) as f32);
+            Vec3::new(x - GRID_SIZE as f32 / 2.0, 0.0, z - GRID_SIZE as f32 / 2.0)
+        })
+        .collect();
+
+    // A travelling wave, standing in for the output of a simulation.
+    let mut animation = PointCloudAnimation::new();
+    for frame in 1..=NUM_FRAMES {
+        let time = frame as f32 / 10.0;
+        let heights: Vec<f32> = positions
+            .iter()
+            .map(|position| 5.0 * (position.length() * 0.2 - time * 3.0).sin())
+            .collect();
+        let offsets = heights.iter().map(|&height| Vec3::Y * height).collect();
+        // The heights are also animated as a scalar, and coloured by the ramp below.
+        animation
+            .insert(AnimationFrame::new(time, FramePoints::Offsets(offsets)).with_scalars(heights));
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    let mut point_cloud = PointCloudAsset::new(mesh);
+    point_cloud.animation = Some(animation);
+    point_cloud.animation_upload = AnimationUpload::AllFrames;
+    let point_cloud = assets.add(point_cloud);
+
+    commands
+        .spawn(PotreePointCloud {
+            mesh: point_cloud,
+            point_size: 1.0,
+            ..default()
+        })
+        .insert(PlaybackControls {
+            playing: true,
+            ..default()
+        })
+        .insert(ScalarColorRamp::new(
+            (-5.0, 5.0),
+            [Color::BLUE, Color::WHITE, Color::RED],
+        ))
+        .insert(SpatialBundle::default());
+}
@@ -0,0 +1,61 @@
+use bevy::prelude::*;
+use bevy_fsc_point_cloud::{PointCloudAsset, PointCloudRenderMode, PotreePointCloud};
+use smooth_bevy_cameras::{
+    controllers::fps::{FpsCameraBundle, FpsCameraController, FpsCameraPlugin},
+    LookTransformPlugin,
+};
+
+fn main() {
+    App::new()
+        .add_plugins((
+            DefaultPlugins.set(WindowPlugin::default()),
+            LookTransformPlugin,
+            FpsCameraPlugin::default(),
+            bevy_fsc_point_cloud::PointCloudPlugin,
+        ))
+        .add_systems(Startup, startup)
+        .add_systems(Update, toggle_render_mode)
+        .run();
+}
+
+fn startup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn(Camera3dBundle::default())
+        .insert(FpsCameraBundle::new(
+            FpsCameraController {
+                translate_sensitivity: 200.0,
+                ..Default::default()
+            },
+            Vec3::new(0.0, 100.0, 0.0),
+            Vec3::new(100.0, 0.0, 100.0),
+            Vec3::Y,
+        ))
+        .insert(PointCloudRenderMode::high_quality_splatting());
+
+    let mesh: Handle<PointCloudAsset> = asset_server.load("laman_mahkota.laz");
+
+    commands
+        .spawn(PotreePointCloud {
+            mesh,
+            point_size: 0.007,
+            ..default()
+        })
+        .insert(SpatialBundle::default());
+}
+
+/// Switches between the standard and the splatting render mode with space, to compare them.
+fn toggle_render_mode(
+    keys: Res<Input<KeyCode>>,
+    mut render_modes: Query<&mut PointCloudRenderMode>,
+) {
+    if !keys.just_pressed(KeyCode::Space) {
+        return;
+    }
+    for mut render_mode in &mut render_modes {
+        *render_mode = match *render_mode {
+            PointCloudRenderMode::Standard => PointCloudRenderMode::high_quality_splatting(),
+            PointCloudRenderMode::HighQualitySplatting { .. } => PointCloudRenderMode::Standard,
+        };
+        info!("Rendering point clouds with {:?}", *render_mode);
+    }
+}
@@ -38,6 +38,7 @@ fn startup(mut commands: Commands, asset_server: Res<AssetServer>) {
         .spawn(PotreePointCloud {
             mesh,
             point_size: 0.007,
+            ..default()
         })
         .insert(SpatialBundle::default());
 
@@ -45,6 +46,7 @@ fn startup(mut commands: Commands, asset_server: Res<AssetServer>) {
         range: ClippingPlaneRange {
             min_sdist: 0.0,
             max_sdist: 0.5,
+            ..default()
         },
         transform: TransformBundle {
             local: Transform::from_translation(Vec3 {
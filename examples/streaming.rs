@@ -0,0 +1,42 @@
+use bevy::prelude::*;
+use bevy_fsc_point_cloud::StreamingPointCloud;
+
+const POINTS_PER_FRAME: usize = 500;
+
+fn main() {
+    App::new()
+        .add_plugins((
+            DefaultPlugins.set(WindowPlugin::default()),
+            bevy_fsc_point_cloud::PointCloudPlugin,
+        ))
+        .add_systems(Startup, startup)
+        .add_systems(Update, scan)
+        .run();
+}
+
+fn startup(mut commands: Commands) {
+    commands.spawn(Camera3dBundle {
+        transform: Transform::from_xyz(0.0, 40.0, 60.0).looking_at(Vec3::ZERO, Vec3::Y),
+        ..default()
+    });
+
+    commands
+        .spawn(StreamingPointCloud::new(200_000, 0.3).with_max_age(4.0, 1.0))
+        .insert(SpatialBundle::default());
+}
+
+/// Pretends to be a spinning sensor, sweeping a line of points around a bumpy ground.
+fn scan(time: Res<Time>, mut point_clouds: Query<&mut StreamingPointCloud>) {
+    let Ok(mut point_cloud) = point_clouds.get_single_mut() else {
+        return;
+    };
+    let angle = time.elapsed_seconds() * 2.0;
+    point_cloud.extend((0..POINTS_PER_FRAME).map(|i| {
+        let distance = 5.0 + i as f32 * 0.08;
+        let angle = angle + i as f32 * 0.001;
+        let (x, z) = (angle.cos() * distance, angle.sin() * distance);
+        let height = (x * 0.3).sin() * (z * 0.2).cos() * 3.0;
+        let color = Color::hsl(distance * 8.0 % 360.0, 0.8, 0.5);
+        (Vec3::new(x, height, z), color)
+    }));
+}
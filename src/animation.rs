@@ -0,0 +1,396 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+
+/// The points of an [`AnimationFrame`].
+#[derive(Clone, Debug)]
+pub enum FramePoints {
+    /// Offsets from the positions of the point cloud mesh.
+    Offsets(Vec<Vec3>),
+    /// Positions in the local space of the point cloud mesh.
+    Positions(Vec<Vec3>),
+    /// Offsets from the positions of the point cloud mesh, stored in 8 bits per component
+    /// like recorded replays. The offset of a point is its components times `scale`.
+    Quantized { scale: Vec3, offsets: Vec<[i8; 3]> },
+}
+
+impl FramePoints {
+    pub fn len(&self) -> usize {
+        match self {
+            FramePoints::Offsets(points) | FramePoints::Positions(points) => points.len(),
+            FramePoints::Quantized { offsets, .. } => offsets.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A keyframe of a [`PointCloudAnimation`].
+#[derive(Clone, Debug)]
+pub struct AnimationFrame {
+    /// The time from the start of the animation, in seconds. The frame is fully shown at
+    /// this time, and interpolated from the previous frame before it.
+    pub time: f32,
+    pub points: FramePoints,
+    /// The colours of the points, in linear RGB. Points without a colour keep the colour of
+    /// their mesh.
+    pub colors: Option<Vec<Vec3>>,
+    /// A scalar attribute of the points, like a temperature, shown through the
+    /// [`ScalarColorRamp`] of the point cloud. Points without a value have a value of zero.
+    pub scalars: Option<Vec<f32>>,
+}
+
+impl AnimationFrame {
+    pub fn new(time: f32, points: FramePoints) -> Self {
+        Self {
+            time,
+            points,
+            colors: None,
+            scalars: None,
+        }
+    }
+
+    pub fn with_colors(mut self, colors: Vec<Vec3>) -> Self {
+        self.colors = Some(colors);
+        self
+    }
+
+    pub fn with_scalars(mut self, scalars: Vec<f32>) -> Self {
+        self.scalars = Some(scalars);
+        self
+    }
+}
+
+/// Maps the [`AnimationFrame::scalars`] of a [`PotreePointCloud`](crate::PotreePointCloud)
+/// to colours, interpolated on the GPU.
+#[derive(Clone, Component, Debug)]
+pub struct ScalarColorRamp {
+    /// The scalar values mapped to the first and last colours.
+    pub range: (f32, f32),
+    /// Colours spread evenly over the range, at most [`ScalarColorRamp::MAX_COLORS`]. Points
+    /// are blended towards the ramp colour by its alpha.
+    pub colors: Vec<Color>,
+}
+
+impl ScalarColorRamp {
+    pub const MAX_COLORS: usize = 8;
+
+    pub fn new(range: (f32, f32), colors: impl IntoIterator<Item = Color>) -> Self {
+        Self {
+            range,
+            colors: colors.into_iter().collect(),
+        }
+    }
+}
+
+/// A per-point animation of a [`PointCloudAsset`](crate::PointCloudAsset), independent of
+/// the format it was loaded from, so animations can also be built procedurally, like the
+/// output of a simulation.
+///
+/// Points move from their mesh positions at the start of the animation towards the first
+/// frame. Frames with fewer points than the mesh leave the remaining points at their mesh
+/// positions. Colours and scalars are interpolated like positions.
+///
+/// The frames are shared between clones, so extracting an animation to the render world
+/// doesn't copy them.
+#[derive(Clone, Debug, Default)]
+pub struct PointCloudAnimation {
+    frames: Arc<Vec<AnimationFrame>>,
+}
+
+impl PointCloudAnimation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an animation from frames in any order.
+    pub fn from_frames(frames: impl IntoIterator<Item = AnimationFrame>) -> Self {
+        let mut frames: Vec<_> = frames.into_iter().collect();
+        frames.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self {
+            frames: Arc::new(frames),
+        }
+    }
+
+    /// Adds a frame, keeping the frames sorted by time. A frame at the time of an existing
+    /// frame replaces it.
+    pub fn insert_frame(&mut self, time: f32, points: FramePoints) {
+        self.insert(AnimationFrame::new(time, points));
+    }
+
+    /// Adds a frame with its attributes, see [`Self::insert_frame`].
+    pub fn insert(&mut self, frame: AnimationFrame) {
+        let frames = Arc::make_mut(&mut self.frames);
+        let index = frames.partition_point(|f| f.time < frame.time);
+        match frames.get_mut(index) {
+            Some(existing) if existing.time == frame.time => *existing = frame,
+            _ => frames.insert(index, frame),
+        }
+    }
+
+    /// Adds a frame, see [`Self::insert_frame`].
+    pub fn with_frame(mut self, time: f32, points: FramePoints) -> Self {
+        self.insert_frame(time, points);
+        self
+    }
+
+    /// The frames, sorted by time.
+    pub fn frames(&self) -> &[AnimationFrame] {
+        &self.frames
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Whether any frame has colours or scalars.
+    pub fn has_attributes(&self) -> bool {
+        self.frames
+            .iter()
+            .any(|frame| frame.colors.is_some() || frame.scalars.is_some())
+    }
+
+    /// The number of floats stored on the GPU for each point of a frame: an offset, then a
+    /// colour offset and a scalar when the animation has attributes.
+    pub(crate) fn floats_per_point(&self) -> usize {
+        if self.has_attributes() {
+            7
+        } else {
+            3
+        }
+    }
+
+    /// The time of the last frame, in seconds.
+    pub fn duration(&self) -> Option<f32> {
+        self.frames.last().map(|frame| frame.time)
+    }
+
+    /// The index of the frame that is played at `time`, the first frame whose time isn't
+    /// before `time`.
+    pub fn frame_index(&self, time: f32) -> Option<usize> {
+        if self.frames.is_empty() {
+            return None;
+        }
+        Some(
+            self.frames
+                .partition_point(|frame| frame.time < time)
+                .min(self.frames.len() - 1),
+        )
+    }
+
+    /// Turns the [`FramePoints::Positions`] frames into offsets from `mesh_positions`, and the
+    /// colours into offsets from `mesh_colors`. Animations of offsets without colours stay
+    /// shared, and quantized offsets stay quantized.
+    pub(crate) fn into_offsets(
+        mut self,
+        mesh_positions: &[[f32; 3]],
+        mesh_colors: Option<&[[f32; 3]]>,
+    ) -> Self {
+        let is_offsets = |frame: &AnimationFrame| {
+            matches!(
+                frame.points,
+                FramePoints::Offsets(_) | FramePoints::Quantized { .. }
+            ) && frame.colors.is_none()
+        };
+        if self.frames.iter().all(is_offsets) {
+            return self;
+        }
+        for frame in Arc::make_mut(&mut self.frames) {
+            if let FramePoints::Positions(positions) = &mut frame.points {
+                for (position, mesh_position) in positions.iter_mut().zip(mesh_positions) {
+                    *position -= Vec3::from(*mesh_position);
+                }
+                frame.points = FramePoints::Offsets(std::mem::take(positions));
+            }
+            if let Some(colors) = &mut frame.colors {
+                colors.truncate(mesh_positions.len());
+                for (i, color) in colors.iter_mut().enumerate() {
+                    *color -= mesh_color(mesh_positions, mesh_colors, i);
+                }
+            }
+        }
+        self
+    }
+
+    /// Removes point `index` from every frame, moving the last point of the mesh into its
+    /// place, like [`PointCloudAsset::remove_points`](crate::PointCloudAsset::remove_points)
+    /// does for the mesh. Frames missing the moved point are filled up to it first.
+    pub(crate) fn swap_remove_point(
+        &mut self,
+        index: usize,
+        mesh_positions: &[[f32; 3]],
+        mesh_colors: Option<&[[f32; 3]]>,
+    ) {
+        let num_points = mesh_positions.len();
+        fn swap_remove<T>(
+            values: &mut Vec<T>,
+            index: usize,
+            num_points: usize,
+            missing: impl Fn(usize) -> T,
+        ) {
+            // A point missing from the frame can only be replaced by another missing point.
+            if index >= values.len() {
+                return;
+            }
+            values.truncate(num_points);
+            let filled = values.len()..num_points;
+            values.extend(filled.map(missing));
+            values.swap_remove(index);
+        }
+        for frame in Arc::make_mut(&mut self.frames) {
+            match &mut frame.points {
+                FramePoints::Offsets(offsets) => {
+                    swap_remove(offsets, index, num_points, |_| Vec3::ZERO)
+                }
+                FramePoints::Positions(positions) => {
+                    swap_remove(positions, index, num_points, |i| {
+                        Vec3::from(mesh_positions[i])
+                    })
+                }
+                FramePoints::Quantized { offsets, .. } => {
+                    swap_remove(offsets, index, num_points, |_| [0; 3])
+                }
+            }
+            if let Some(colors) = &mut frame.colors {
+                swap_remove(colors, index, num_points, |i| {
+                    mesh_color(mesh_positions, mesh_colors, i)
+                });
+            }
+            if let Some(scalars) = &mut frame.scalars {
+                swap_remove(scalars, index, num_points, |_| 0.0);
+            }
+        }
+    }
+
+    /// Writes the offsets and attributes of frame `index` into `view`, which must be zeroed,
+    /// [`Self::floats_per_point`] floats per point. Must be called after [`Self::into_offsets`].
+    pub(crate) fn write_frame_offsets(&self, index: usize, view: &mut [f32]) {
+        let frame = &self.frames[index];
+        let stride = self.floats_per_point();
+        match &frame.points {
+            FramePoints::Offsets(offsets) => {
+                for (offset, view) in offsets.iter().zip(view.chunks_exact_mut(stride)) {
+                    view[..3].copy_from_slice(&offset.to_array());
+                }
+            }
+            FramePoints::Quantized { scale, offsets } => {
+                for (offset, view) in offsets.iter().zip(view.chunks_exact_mut(stride)) {
+                    let offset = Vec3::from(offset.map(f32::from)) * *scale;
+                    view[..3].copy_from_slice(&offset.to_array());
+                }
+            }
+            FramePoints::Positions(_) => {
+                unreachable!("frame positions should have been turned into offsets")
+            }
+        }
+        if let Some(colors) = &frame.colors {
+            for (color, view) in colors.iter().zip(view.chunks_exact_mut(stride)) {
+                view[3..6].copy_from_slice(&color.to_array());
+            }
+        }
+        if let Some(scalars) = &frame.scalars {
+            for (&scalar, view) in scalars.iter().zip(view.chunks_exact_mut(stride)) {
+                view[6] = scalar;
+            }
+        }
+    }
+}
+
+/// The colour of mesh point `i`. Matches the colour the shader gives to points of meshes
+/// without colours.
+fn mesh_color(mesh_positions: &[[f32; 3]], mesh_colors: Option<&[[f32; 3]]>, i: usize) -> Vec3 {
+    match mesh_colors {
+        Some(colors) => Vec3::from(colors[i]),
+        None => Vec3::from(mesh_positions[i]) % 1.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESH_POSITIONS: [[f32; 3]; 4] = [[0.0; 3], [1.0; 3], [2.0; 3], [3.0; 3]];
+    const MESH_COLORS: [[f32; 3]; 4] = [[0.1; 3], [0.2; 3], [0.3; 3], [0.4; 3]];
+
+    fn animation(frame: AnimationFrame) -> PointCloudAnimation {
+        PointCloudAnimation::from_frames([frame])
+    }
+
+    #[test]
+    fn swap_remove_point_fills_short_frames_from_the_mesh() {
+        let mut animation = animation(
+            AnimationFrame::new(1.0, FramePoints::Positions(vec![Vec3::X, Vec3::Y]))
+                .with_colors(vec![Vec3::ONE])
+                .with_scalars(vec![5.0, 6.0]),
+        );
+        animation.swap_remove_point(0, &MESH_POSITIONS, Some(&MESH_COLORS));
+        let frame = &animation.frames()[0];
+        let FramePoints::Positions(positions) = &frame.points else {
+            unreachable!();
+        };
+        // The last point of the mesh wasn't in the frame, so it is at its mesh position.
+        assert_eq!(positions, &[Vec3::splat(3.0), Vec3::Y, Vec3::splat(2.0)]);
+        assert_eq!(
+            frame.colors.as_deref().unwrap(),
+            &[Vec3::splat(0.4), Vec3::splat(0.2), Vec3::splat(0.3)]
+        );
+        assert_eq!(frame.scalars.as_deref().unwrap(), &[0.0, 6.0, 0.0]);
+    }
+
+    #[test]
+    fn swap_remove_point_missing_from_the_frame() {
+        let offsets = vec![Vec3::X, Vec3::Y];
+        let mut animation = animation(
+            AnimationFrame::new(1.0, FramePoints::Offsets(offsets.clone())).with_scalars(vec![5.0]),
+        );
+        animation.swap_remove_point(2, &MESH_POSITIONS, None);
+        let frame = &animation.frames()[0];
+        let FramePoints::Offsets(new_offsets) = &frame.points else {
+            unreachable!();
+        };
+        assert_eq!(new_offsets, &offsets);
+        assert_eq!(frame.scalars.as_deref().unwrap(), &[5.0]);
+    }
+
+    #[test]
+    fn swap_remove_point_truncates_long_frames() {
+        let mut animation = animation(AnimationFrame::new(
+            1.0,
+            FramePoints::Quantized {
+                scale: Vec3::ONE,
+                offsets: vec![[1; 3], [2; 3], [3; 3], [4; 3], [5; 3]],
+            },
+        ));
+        animation.swap_remove_point(1, &MESH_POSITIONS, None);
+        let FramePoints::Quantized { offsets, .. } = &animation.frames()[0].points else {
+            unreachable!();
+        };
+        assert_eq!(offsets, &[[1; 3], [4; 3], [3; 3]]);
+    }
+
+    #[test]
+    fn write_frame_offsets_of_short_frames() {
+        let animation = animation(
+            AnimationFrame::new(
+                1.0,
+                FramePoints::Quantized {
+                    scale: Vec3::new(0.5, 1.0, 2.0),
+                    offsets: vec![[2, -1, 1]],
+                },
+            )
+            .with_scalars(vec![3.0]),
+        );
+        let mut view = vec![0.0; 2 * animation.floats_per_point()];
+        animation.write_frame_offsets(0, &mut view);
+        assert_eq!(
+            view,
+            [1.0, -1.0, 2.0, 0.0, 0.0, 0.0, 3.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]
+        );
+    }
+}
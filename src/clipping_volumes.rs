@@ -0,0 +1,319 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{ShaderType, StorageBuffer},
+        Extract,
+    },
+};
+
+use crate::{
+    clippling_planes::{
+        distribute_clipping_primitives, ClippingPlaneTargets, ClippingRanges, ClippingScope,
+    },
+    PotreePointCloud, StreamingPointCloud,
+};
+
+/// Whether the points inside or outside of a clipping volume are kept.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClippingMode {
+    /// Only points inside the volume are visible.
+    #[default]
+    Include,
+    /// Only points outside the volume are visible.
+    Exclude,
+}
+
+/// An oriented box centered on the entity's [`GlobalTransform`].
+#[derive(Clone, Component, Debug)]
+pub struct ClippingBox {
+    pub half_extents: Vec3,
+    pub mode: ClippingMode,
+}
+
+impl Default for ClippingBox {
+    fn default() -> Self {
+        Self {
+            half_extents: Vec3::splat(0.5),
+            mode: ClippingMode::Include,
+        }
+    }
+}
+
+/// A sphere centered on the entity's [`GlobalTransform`].
+#[derive(Clone, Component, Debug)]
+pub struct ClippingSphere {
+    pub radius: f32,
+    pub mode: ClippingMode,
+}
+
+impl Default for ClippingSphere {
+    fn default() -> Self {
+        Self {
+            radius: 0.5,
+            mode: ClippingMode::Include,
+        }
+    }
+}
+
+/// A capped cylinder centered on the entity's [`GlobalTransform`].
+///
+/// The cylinder axis is the local Y axis, so an untransformed cylinder is vertical.
+#[derive(Clone, Component, Debug)]
+pub struct ClippingCylinder {
+    pub radius: f32,
+    pub half_height: f32,
+    pub mode: ClippingMode,
+}
+
+impl Default for ClippingCylinder {
+    fn default() -> Self {
+        Self {
+            radius: 0.5,
+            half_height: f32::INFINITY,
+            mode: ClippingMode::Include,
+        }
+    }
+}
+
+/// The set of local positions `p` where `normal.dot(p) <= distance`.
+#[derive(Clone, Copy, Debug)]
+pub struct ClippingHalfSpace {
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+impl ClippingHalfSpace {
+    pub fn new(normal: Vec3, distance: f32) -> Self {
+        Self { normal, distance }
+    }
+
+    /// The half space bounded by the plane through `point`, with `outward_normal` pointing away from the inside.
+    pub fn from_point_normal(point: Vec3, outward_normal: Vec3) -> Self {
+        let normal = outward_normal.normalize();
+        Self {
+            normal,
+            distance: normal.dot(point),
+        }
+    }
+}
+
+/// A convex polytope, described as the intersection of half spaces in the entity's local space.
+#[derive(Clone, Component, Debug, Default)]
+pub struct ClippingPolytope {
+    pub half_spaces: Vec<ClippingHalfSpace>,
+    pub mode: ClippingMode,
+}
+
+/// The space a [`ClippingPolygon`]'s vertices are expressed in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ClippingPolygonSpace {
+    /// The local XZ plane of the entity's [`GlobalTransform`], with vertex `(x, y)` at local
+    /// position `(x, 0, y)`. The polygon is extruded infinitely along the local Y axis.
+    #[default]
+    Local,
+    /// Logical viewport coordinates of the given camera, as used by
+    /// [`Camera::viewport_to_world`]. The polygon is extruded along the camera's view rays,
+    /// like a lasso selection.
+    Viewport(Entity),
+}
+
+/// A simple polygon, extruded into a prism that clips point clouds.
+///
+/// The polygon doesn't need to be convex, but its edges must not intersect.
+#[derive(Clone, Component, Debug, Default)]
+pub struct ClippingPolygon {
+    pub vertices: Vec<Vec2>,
+    pub space: ClippingPolygonSpace,
+    pub mode: ClippingMode,
+}
+
+const CLIPPING_VOLUME_BOX: u32 = 0;
+const CLIPPING_VOLUME_SPHERE: u32 = 1;
+const CLIPPING_VOLUME_CYLINDER: u32 = 2;
+const CLIPPING_VOLUME_POLYTOPE: u32 = 3;
+const CLIPPING_VOLUME_POLYGON: u32 = 4;
+
+/// Maps local `(x, y, z)` to `(x, z, y)`, so a [`ClippingPolygonSpace::Local`] polygon lies in the XY plane.
+const SWAP_YZ: Mat4 = Mat4::from_cols(Vec4::X, Vec4::Z, Vec4::Y, Vec4::W);
+
+#[derive(Clone, Debug, Default, ShaderType)]
+pub(crate) struct GpuClippingVolume {
+    /// For polygons, maps to homogeneous coordinates whose XY plane holds the polygon.
+    pub world_to_local: Mat4,
+    pub kind: u32,
+    /// 0 to keep the inside, 1 to keep the outside.
+    pub exclude: u32,
+    /// Range in the clipping elements buffer, holding half spaces for polytopes or
+    /// vertices (in `xy`) for polygons.
+    pub first_element: u32,
+    pub num_elements: u32,
+    /// Box: half extents. Sphere: radius in `x`. Cylinder: radius in `x`, half height in `y`.
+    pub params: Vec4,
+}
+
+#[derive(Resource, Default)]
+pub struct StorageBufferOfGpuClippingVolumes(pub(crate) StorageBuffer<Vec<GpuClippingVolume>>);
+
+/// Half spaces of [`ClippingPolytope`]s and vertices of [`ClippingPolygon`]s, shared by every point cloud.
+#[derive(Resource, Default)]
+pub struct StorageBufferOfClippingElements(pub(crate) StorageBuffer<Vec<Vec4>>);
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn extract_clipping_volumes(
+    volumes: Extract<
+        Query<(
+            &GlobalTransform,
+            AnyOf<(
+                &ClippingBox,
+                &ClippingSphere,
+                &ClippingCylinder,
+                &ClippingPolytope,
+                &ClippingPolygon,
+            )>,
+            Option<&ClippingPlaneTargets>,
+            Option<&Parent>,
+        )>,
+    >,
+    cameras: Extract<Query<(&Camera, &GlobalTransform)>>,
+    point_clouds: Extract<Query<Entity, Or<(With<PotreePointCloud>, With<StreamingPointCloud>)>>>,
+    mut clipping_volume_buffer: ResMut<StorageBufferOfGpuClippingVolumes>,
+    mut clipping_element_buffer: ResMut<StorageBufferOfClippingElements>,
+    mut ranges: ResMut<ClippingRanges>,
+) {
+    let elements = clipping_element_buffer.0.get_mut();
+    elements.clear();
+
+    let mut gpu_volumes = Vec::new();
+    for (transform, (cuboid, sphere, cylinder, polytope, polygon), targets, parent) in
+        volumes.iter()
+    {
+        let world_to_local = transform.compute_matrix().inverse();
+        let parent = parent
+            .map(Parent::get)
+            .filter(|&p| point_clouds.contains(p));
+        // An entity may carry several volume components, each one is extracted separately.
+        let mut push_volume = |volume: GpuClippingVolume| {
+            gpu_volumes.push((volume, ClippingScope::new(targets, parent)));
+        };
+        if let Some(cuboid) = cuboid {
+            push_volume(GpuClippingVolume {
+                world_to_local,
+                kind: CLIPPING_VOLUME_BOX,
+                exclude: (cuboid.mode == ClippingMode::Exclude) as u32,
+                params: cuboid.half_extents.extend(0.0),
+                ..default()
+            });
+        }
+        if let Some(sphere) = sphere {
+            push_volume(GpuClippingVolume {
+                world_to_local,
+                kind: CLIPPING_VOLUME_SPHERE,
+                exclude: (sphere.mode == ClippingMode::Exclude) as u32,
+                params: Vec4::new(sphere.radius, 0.0, 0.0, 0.0),
+                ..default()
+            });
+        }
+        if let Some(cylinder) = cylinder {
+            push_volume(GpuClippingVolume {
+                world_to_local,
+                kind: CLIPPING_VOLUME_CYLINDER,
+                exclude: (cylinder.mode == ClippingMode::Exclude) as u32,
+                params: Vec4::new(cylinder.radius, cylinder.half_height, 0.0, 0.0),
+                ..default()
+            });
+        }
+        if let Some(polytope) = polytope {
+            let first_element = elements.len() as u32;
+            elements.extend(
+                polytope
+                    .half_spaces
+                    .iter()
+                    .map(|half_space| half_space.normal.extend(half_space.distance)),
+            );
+            push_volume(GpuClippingVolume {
+                world_to_local,
+                kind: CLIPPING_VOLUME_POLYTOPE,
+                exclude: (polytope.mode == ClippingMode::Exclude) as u32,
+                first_element,
+                num_elements: polytope.half_spaces.len() as u32,
+                ..default()
+            });
+        }
+        if let Some(polygon) = polygon {
+            if let Some((world_to_polygon, vertices)) =
+                polygon_to_gpu(polygon, &world_to_local, &cameras)
+            {
+                let first_element = elements.len() as u32;
+                elements.extend(
+                    vertices
+                        .into_iter()
+                        .map(|vertex| vertex.extend(0.0).extend(0.0)),
+                );
+                push_volume(GpuClippingVolume {
+                    world_to_local: world_to_polygon,
+                    kind: CLIPPING_VOLUME_POLYGON,
+                    exclude: (polygon.mode == ClippingMode::Exclude) as u32,
+                    first_element,
+                    num_elements: polygon.vertices.len() as u32,
+                    ..default()
+                });
+            }
+        }
+    }
+    if elements.is_empty() {
+        // Storage buffer bindings can't be empty.
+        elements.push(Vec4::ZERO);
+    }
+
+    let volumes = clipping_volume_buffer.0.get_mut();
+    volumes.clear();
+    distribute_clipping_primitives(
+        &gpu_volumes,
+        point_clouds.iter(),
+        volumes,
+        &mut ranges.volumes,
+    );
+}
+
+/// Computes the matrix that maps world positions onto the polygon's plane, and the polygon
+/// vertices in that plane. Returns `None` if the polygon's camera can't be found.
+fn polygon_to_gpu(
+    polygon: &ClippingPolygon,
+    world_to_local: &Mat4,
+    cameras: &Query<(&Camera, &GlobalTransform)>,
+) -> Option<(Mat4, Vec<Vec2>)> {
+    match polygon.space {
+        ClippingPolygonSpace::Local => Some((SWAP_YZ * *world_to_local, polygon.vertices.clone())),
+        ClippingPolygonSpace::Viewport(camera_entity) => {
+            let (camera, camera_transform) = cameras.get(camera_entity).ok()?;
+            let viewport_size = camera.logical_viewport_size()?;
+            let world_to_clip =
+                camera.projection_matrix() * camera_transform.compute_matrix().inverse();
+            let vertices = polygon
+                .vertices
+                .iter()
+                .map(|&vertex| {
+                    // Flip the Y co-ordinate origin from the top to the bottom.
+                    let vertex = Vec2::new(vertex.x, viewport_size.y - vertex.y);
+                    vertex * 2. / viewport_size - Vec2::ONE
+                })
+                .collect();
+            Some((world_to_clip, vertices))
+        }
+    }
+}
+
+pub(crate) fn prepare_clipping_volumes(
+    render_device: Res<bevy::render::renderer::RenderDevice>,
+    render_queue: Res<bevy::render::renderer::RenderQueue>,
+    mut clipping_volume_buffer: ResMut<StorageBufferOfGpuClippingVolumes>,
+    mut clipping_element_buffer: ResMut<StorageBufferOfClippingElements>,
+) {
+    // Values already pushed in extract stage.
+    clipping_volume_buffer
+        .0
+        .write_buffer(&render_device, &render_queue);
+    clipping_element_buffer
+        .0
+        .write_buffer(&render_device, &render_queue);
+}
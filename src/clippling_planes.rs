@@ -1,21 +1,63 @@
 use bevy::{
     prelude::*,
     render::{
-        render_resource::{ShaderType, UniformBuffer},
+        render_resource::{ShaderType, StorageBuffer},
         Extract,
     },
+    utils::HashMap,
 };
 
+use crate::{PotreePointCloud, StreamingPointCloud};
+
 /// The range of signed distances from the plane that don't get clipped.
 ///
 /// The plane origin and normal will be extracted from the [`GlobalTransform`],
 /// assuming normal axis is pointing
-#[derive(Clone, Component, Debug, ShaderType)]
+///
+/// By default a point must be inside of every range to be visible. Ranges that
+/// share a [`union_group`](Self::union_group) are combined first, so a point only
+/// needs to be inside of one of them, which allows showing several disjoint slices.
+#[derive(Clone, Component, Debug)]
 pub struct ClippingPlaneRange {
     /// The minimum (signed) distance from a visible point's centroid to the plane.
     pub min_sdist: f32,
     /// The maximum (signed) distance from a visible point's centroid to the plane.
     pub max_sdist: f32,
+    /// Ranges in the same group are combined with OR instead of AND.
+    pub union_group: Option<u32>,
+    /// How points outside of the range are drawn.
+    pub highlight: ClippingHighlight,
+}
+
+/// How points clipped by a [`ClippingPlaneRange`] are drawn.
+///
+/// When several ranges clip the same point, all of their highlights are applied, and
+/// the point is hidden if any of them hides it.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum ClippingHighlight {
+    /// Clipped points are hidden.
+    #[default]
+    Discard,
+    /// Clipped points are blended towards `color`, by a `strength` between 0 and 1.
+    Tint { color: Color, strength: f32 },
+    /// The colour of clipped points is multiplied by `brightness`.
+    Dim { brightness: f32 },
+    /// Clipped points within `width` of the cut surface are drawn with `color`,
+    /// the others are hidden.
+    Band { color: Color, width: f32 },
+}
+
+impl ClippingHighlight {
+    /// The `(mode, param, color)` triple read by the shader, modes match the
+    /// `CLIPPING_HIGHLIGHT_*` constants of `shader.vert`.
+    fn to_gpu(self) -> (u32, f32, Vec4) {
+        match self {
+            Self::Discard => (0, 0.0, Vec4::ZERO),
+            Self::Tint { color, strength } => (1, strength, color.as_linear_rgba_f32().into()),
+            Self::Dim { brightness } => (2, brightness, Vec4::ZERO),
+            Self::Band { color, width } => (3, width, color.as_linear_rgba_f32().into()),
+        }
+    }
 }
 
 impl Default for ClippingPlaneRange {
@@ -23,6 +65,8 @@ impl Default for ClippingPlaneRange {
         Self {
             min_sdist: 0.0,
             max_sdist: f32::INFINITY,
+            union_group: None,
+            highlight: ClippingHighlight::Discard,
         }
     }
 }
@@ -33,60 +77,161 @@ pub struct ClippingPlaneBundle {
     pub transform: TransformBundle,
 }
 
-#[derive(Clone, Component, Debug, Default, ShaderType)]
+/// Restricts a clipping plane or clipping volume to a set of [`PotreePointCloud`] entities.
+///
+/// Clipping primitives without this component apply to every point cloud, unless
+/// they are a child of a [`PotreePointCloud`] entity, in which case they only
+/// apply to their parent.
+#[derive(Clone, Component, Debug, Default)]
+pub struct ClippingPlaneTargets {
+    pub point_clouds: Vec<Entity>,
+}
+
+impl ClippingPlaneTargets {
+    pub fn new(point_clouds: impl IntoIterator<Item = Entity>) -> Self {
+        Self {
+            point_clouds: point_clouds.into_iter().collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, ShaderType)]
 pub(crate) struct GpuClippingPlaneRange {
     pub origin: Vec3,
     pub unit_normal: Vec3,
     pub min_sdist: f32,
     pub max_sdist: f32,
+    /// [`NO_UNION_GROUP`] for ranges that are intersected with every other range.
+    pub union_group: u32,
+    /// See [`ClippingHighlight::to_gpu`].
+    pub highlight_mode: u32,
+    pub highlight_param: f32,
+    pub highlight_color: Vec4,
 }
 
-#[derive(Debug, Default, ShaderType)]
-pub(crate) struct GpuClippingPlaneRanges {
-    pub ranges: [GpuClippingPlaneRange; MAX_CLIPPING_PLANES],
-    pub num_ranges: u32,
+pub(crate) const NO_UNION_GROUP: u32 = u32::MAX;
+
+#[derive(Resource, Default)]
+pub struct StorageBufferOfGpuClippingPlaneRanges(
+    pub(crate) StorageBuffer<Vec<GpuClippingPlaneRange>>,
+);
+
+/// Which point clouds a clipping primitive applies to.
+pub(crate) enum ClippingScope {
+    Global,
+    PointClouds(Vec<Entity>),
 }
 
-/// The clipping shader is `O(planes * points)`, so we set a reasonable limit.
-pub const MAX_CLIPPING_PLANES: usize = 16;
+impl ClippingScope {
+    /// `parent` is the parent of the primitive, if that parent is a point cloud.
+    pub fn new(targets: Option<&ClippingPlaneTargets>, parent: Option<Entity>) -> Self {
+        match (targets, parent) {
+            (Some(targets), _) => Self::PointClouds(targets.point_clouds.clone()),
+            (None, Some(parent)) => Self::PointClouds(vec![parent]),
+            (None, None) => Self::Global,
+        }
+    }
 
-#[derive(Resource, Default)]
-pub struct UniformBufferOfGpuClippingPlaneRanges(pub(crate) UniformBuffer<GpuClippingPlaneRanges>);
+    pub fn contains(&self, point_cloud: Entity) -> bool {
+        match self {
+            Self::Global => true,
+            Self::PointClouds(point_clouds) => point_clouds.contains(&point_cloud),
+        }
+    }
+}
 
-pub(crate) fn extract_clipping_planes(
-    clipping_planes: Extract<Query<(&ClippingPlaneRange, &GlobalTransform)>>,
-    mut clipping_plane_uniform: ResMut<UniformBufferOfGpuClippingPlaneRanges>,
+/// The range of a clipping storage buffer used by one point cloud.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ClippingRange {
+    pub first: u32,
+    pub count: u32,
+}
+
+/// Copies every primitive into `gpu_items`, once for each point cloud it applies to,
+/// so that each point cloud reads a contiguous range of the buffer.
+pub(crate) fn distribute_clipping_primitives<T: Clone + Default>(
+    primitives: &[(T, ClippingScope)],
+    point_clouds: impl Iterator<Item = Entity>,
+    gpu_items: &mut Vec<T>,
+    ranges: &mut HashMap<Entity, ClippingRange>,
 ) {
-    let mut iter = clipping_planes.iter();
-    let mut gpu_planes = GpuClippingPlaneRanges::default();
-    for (range, transform) in iter.by_ref() {
-        let (_, rotation, translation) = transform.to_scale_rotation_translation();
-        gpu_planes.ranges[gpu_planes.num_ranges as usize] = GpuClippingPlaneRange {
-            origin: translation,
-            unit_normal: rotation * Vec3::X,
-            min_sdist: range.min_sdist,
-            max_sdist: range.max_sdist,
-        };
-        gpu_planes.num_ranges += 1;
-        if gpu_planes.num_ranges as usize == MAX_CLIPPING_PLANES {
-            break;
+    ranges.clear();
+    for point_cloud in point_clouds {
+        let first = gpu_items.len() as u32;
+        gpu_items.extend(
+            primitives
+                .iter()
+                .filter(|(_, scope)| scope.contains(point_cloud))
+                .map(|(item, _)| item.clone()),
+        );
+        let count = gpu_items.len() as u32 - first;
+        if count > 0 {
+            ranges.insert(point_cloud, ClippingRange { first, count });
         }
     }
-    if iter.next().is_some() {
-        warn!(
-            "Too many GpuClippingPlaneRanges entities, at most {MAX_CLIPPING_PLANES} are supported"
-        );
+    if gpu_items.is_empty() {
+        // Storage buffer bindings can't be empty.
+        gpu_items.push(T::default());
     }
-    clipping_plane_uniform.0.set(gpu_planes);
+}
+
+/// The ranges of the clipping storage buffers used by each point cloud.
+#[derive(Resource, Default)]
+pub(crate) struct ClippingRanges {
+    pub planes: HashMap<Entity, ClippingRange>,
+    pub volumes: HashMap<Entity, ClippingRange>,
+}
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn extract_clipping_planes(
+    clipping_planes: Extract<
+        Query<(
+            &ClippingPlaneRange,
+            &GlobalTransform,
+            Option<&ClippingPlaneTargets>,
+            Option<&Parent>,
+        )>,
+    >,
+    point_clouds: Extract<Query<Entity, Or<(With<PotreePointCloud>, With<StreamingPointCloud>)>>>,
+    mut clipping_plane_buffer: ResMut<StorageBufferOfGpuClippingPlaneRanges>,
+    mut ranges: ResMut<ClippingRanges>,
+) {
+    let mut planes: Vec<_> = clipping_planes
+        .iter()
+        .map(|(range, transform, targets, parent)| {
+            let (_, rotation, translation) = transform.to_scale_rotation_translation();
+            let (highlight_mode, highlight_param, highlight_color) = range.highlight.to_gpu();
+            let gpu_plane = GpuClippingPlaneRange {
+                origin: translation,
+                unit_normal: rotation * Vec3::X,
+                min_sdist: range.min_sdist,
+                max_sdist: range.max_sdist,
+                union_group: range.union_group.unwrap_or(NO_UNION_GROUP),
+                highlight_mode,
+                highlight_param,
+                highlight_color,
+            };
+            let parent = parent
+                .map(Parent::get)
+                .filter(|&p| point_clouds.contains(p));
+            (gpu_plane, ClippingScope::new(targets, parent))
+        })
+        .collect();
+    // The shader expects the ranges of a union group to be next to each other.
+    planes.sort_by_key(|(plane, _)| plane.union_group);
+
+    let gpu_planes = clipping_plane_buffer.0.get_mut();
+    gpu_planes.clear();
+    distribute_clipping_primitives(&planes, point_clouds.iter(), gpu_planes, &mut ranges.planes);
 }
 
 pub(crate) fn prepare_clipping_planes(
     render_device: Res<bevy::render::renderer::RenderDevice>,
     render_queue: Res<bevy::render::renderer::RenderQueue>,
-    mut clipping_plane_uniform: ResMut<UniformBufferOfGpuClippingPlaneRanges>,
+    mut clipping_plane_buffer: ResMut<StorageBufferOfGpuClippingPlaneRanges>,
 ) {
     // Values already pushed in extract stage.
-    clipping_plane_uniform
+    clipping_plane_buffer
         .0
         .write_buffer(&render_device, &render_queue);
 }
@@ -1,18 +1,27 @@
+use std::{ops::Range, sync::Mutex};
+
+use crate::PointCloudAnimation;
 use bevy::{
     asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
     prelude::*,
     reflect::TypePath,
     render::{
-        mesh::MeshVertexAttribute,
+        mesh::{MeshVertexAttribute, MeshVertexAttributeId, VertexAttributeValues},
         render_resource::{PrimitiveTopology, VertexFormat},
     },
     utils::thiserror::{self, Error},
 };
 use las::Read;
-use opd_parser::Frames;
 
 pub const ATTRIBUTE_COLOR: MeshVertexAttribute =
     MeshVertexAttribute::new("Vertex_Color", 1, VertexFormat::Float32x3);
+/// The normals of the points, see [`PointCloudAsset::estimate_normals`].
+pub const ATTRIBUTE_NORMAL: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Normal", 2, VertexFormat::Float32x3);
+/// The opacity of each point, multiplied with [`PotreePointCloud::opacity`](crate::PotreePointCloud::opacity).
+/// Point clouds with this attribute are always drawn as transparent.
+pub const ATTRIBUTE_ALPHA: MeshVertexAttribute =
+    MeshVertexAttribute::new("Vertex_Alpha", 3, VertexFormat::Float32);
 
 #[repr(transparent)]
 struct Point {
@@ -44,22 +53,348 @@ impl Point {
     }
 }
 
+/// The points of a [`PotreePointCloud`](crate::PotreePointCloud), created with
+/// [`PointCloudAsset::new`].
 #[derive(Asset, Clone, TypePath)]
 pub struct PointCloudAsset {
-    pub mesh: Mesh,
-    pub animation: Option<Frames>,
-    pub animation_scale: Vec3,
+    /// See [`Self::mesh`] and [`Self::mesh_mut`], which keep track of the edited points.
+    pub(crate) mesh: Mesh,
+    pub animation: Option<PointCloudAnimation>,
+    /// Named times of the animation, sorted by time.
+    pub markers: Vec<TimelineMarker>,
+    pub animation_upload: AnimationUpload,
+    /// See [`Self::set_usage`].
+    usage: PointCloudAssetUsage,
+    pub(crate) render_world_mesh: RenderWorldMesh,
+    /// The points edited since the asset was last extracted, see [`Self::set_positions`].
+    pub(crate) changed_points: ChangedPoints,
+}
+
+/// Ranges of points to upload again, taken when the asset is extracted. `None` once the mesh
+/// was edited without recording the points, see [`PointCloudAsset::mesh_mut`].
+pub(crate) struct ChangedPoints(Mutex<Option<Vec<Range<u32>>>>);
+
+impl Default for ChangedPoints {
+    fn default() -> Self {
+        Self(Mutex::new(Some(Vec::new())))
+    }
+}
+
+impl Clone for ChangedPoints {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.0.lock().unwrap().clone()))
+    }
+}
+
+impl ChangedPoints {
+    fn insert(&mut self, range: Range<u32>) {
+        let Some(ranges) = self.0.get_mut().unwrap() else {
+            return;
+        };
+        if range.is_empty() {
+            return;
+        }
+        match ranges.last_mut() {
+            Some(last) if range.start <= last.end && last.start <= range.end => {
+                *last = last.start.min(range.start)..last.end.max(range.end);
+            }
+            _ => ranges.push(range),
+        }
+    }
+
+    fn insert_unrecorded(&mut self) {
+        *self.0.get_mut().unwrap() = None;
+    }
+
+    /// Takes the ranges recorded since the last call, `None` if an edit wasn't recorded.
+    /// The ranges are empty if only the number of points changed.
+    pub(crate) fn take(&self) -> Option<Vec<Range<u32>>> {
+        self.0.lock().unwrap().replace(Vec::new())
+    }
+}
+
+/// The mesh of a [`PointCloudAssetUsage::RenderWorld`] asset, moved out of the main world
+/// until it is extracted. Behind a lock, so extracting it doesn't modify the asset.
+#[derive(Default)]
+pub(crate) struct RenderWorldMesh(Mutex<MovedMesh>);
+
+#[derive(Clone, Default)]
+enum MovedMesh {
+    #[default]
+    Kept,
+    Pending(Box<Mesh>),
+    Extracted,
+}
+
+impl Clone for RenderWorldMesh {
+    fn clone(&self) -> Self {
+        Self(Mutex::new(self.0.lock().unwrap().clone()))
+    }
+}
+
+impl RenderWorldMesh {
+    /// Takes the mesh to extract, `None` if it was already extracted.
+    pub(crate) fn take(&self) -> Option<Mesh> {
+        let mut moved = self.0.lock().unwrap();
+        match std::mem::replace(&mut *moved, MovedMesh::Extracted) {
+            MovedMesh::Pending(mesh) => Some(*mesh),
+            other => {
+                *moved = other;
+                None
+            }
+        }
+    }
+}
+
+/// How the frames of an animated [`PointCloudAsset`] are uploaded to the GPU.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AnimationUpload {
+    /// Only the frames around the played time are uploaded, whenever it moves to another
+    /// frame. Uses little memory, but seeking far away uploads several frames.
+    #[default]
+    OnSeek,
+    /// Every frame is uploaded once when the asset is prepared, so scrubbing through the
+    /// animation only updates a few indices. Uses `12 * num_points * num_frames` bytes of GPU
+    /// memory, falls back to [`AnimationUpload::OnSeek`] if the frames don't fit in a storage
+    /// buffer binding.
+    AllFrames,
+}
+
+/// Which worlds keep the points of a [`PointCloudAsset`], like Bevy's `RenderAssetUsages`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PointCloudAssetUsage {
+    /// The mesh is kept in the main world after it is uploaded, for picking, the spatial
+    /// index, selections, measurements and editing.
+    #[default]
+    MainAndRenderWorld,
+    /// The mesh is moved out of the main world, leaving an empty mesh, and into the render
+    /// world when the asset is extracted, so the points are only kept on the GPU. The
+    /// animation and markers are kept, so playback still works, but later changes of the
+    /// asset aren't uploaded; add a new asset instead.
+    ///
+    /// Everything reading the points in the main world sees none: the
+    /// [`PointCloudSpatialIndex`](crate::PointCloudSpatialIndex) and its queries, the colour of
+    /// [`PointCloudPickEvent`](crate::PointCloudPickEvent) hits, selections and the volumes
+    /// and profiles of measurements. Picking itself still works, it reads the GPU.
+    RenderWorld,
+}
+
+/// A named time of a [`PointCloudAsset`] animation, for annotating events of a replay.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimelineMarker {
+    pub name: String,
+    /// The time from the start of the animation, in seconds.
+    pub time: f32,
 }
 
 impl PointCloudAsset {
+    pub fn new(mesh: Mesh) -> Self {
+        Self {
+            mesh,
+            animation: None,
+            markers: Vec::new(),
+            animation_upload: AnimationUpload::default(),
+            usage: PointCloudAssetUsage::default(),
+            render_world_mesh: RenderWorldMesh::default(),
+            changed_points: ChangedPoints::default(),
+        }
+    }
+
+    /// The animation, unless it has no frames.
+    fn frames(&self) -> Option<&PointCloudAnimation> {
+        self.animation
+            .as_ref()
+            .filter(|animation| !animation.is_empty())
+    }
+
     pub fn animation_duration(&self) -> Option<f32> {
-        match &self.animation {
-            Some(Frames::I8(frames)) => Some(frames.last().unwrap().time / 1000.),
-            _ => None,
+        self.frames()?.duration()
+    }
+
+    /// The timestamp of each animation frame, in seconds. A frame is fully shown at its
+    /// timestamp, and interpolated from the previous frame before it.
+    pub fn frame_times(&self) -> Vec<f32> {
+        self.frames().map_or_else(Vec::new, |animation| {
+            animation.frames().iter().map(|frame| frame.time).collect()
+        })
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames().map_or(0, PointCloudAnimation::len)
+    }
+
+    /// The timestamp of frame `index`, in seconds.
+    pub fn frame_time(&self, index: usize) -> Option<f32> {
+        Some(self.frames()?.frames().get(index)?.time)
+    }
+
+    /// The index of the animation frame that is played at `time`, the first frame whose
+    /// timestamp isn't before `time`.
+    pub fn frame_index(&self, time: f32) -> Option<usize> {
+        self.frames()?.frame_index(time)
+    }
+
+    /// Adds a marker, keeping the markers sorted by time.
+    pub fn add_marker(&mut self, name: impl Into<String>, time: f32) {
+        let index = self.markers.partition_point(|marker| marker.time <= time);
+        self.markers.insert(
+            index,
+            TimelineMarker {
+                name: name.into(),
+                time,
+            },
+        );
+    }
+
+    pub fn marker(&self, name: &str) -> Option<&TimelineMarker> {
+        self.markers.iter().find(|marker| marker.name == name)
+    }
+
+    pub fn usage(&self) -> PointCloudAssetUsage {
+        self.usage
+    }
+
+    /// Sets which worlds keep the points. [`PointCloudAssetUsage::RenderWorld`] moves the
+    /// mesh out of the main world right away. Going back to
+    /// [`PointCloudAssetUsage::MainAndRenderWorld`] before the asset is extracted restores the
+    /// mesh, afterwards the empty main world mesh is uploaded instead.
+    pub fn set_usage(&mut self, usage: PointCloudAssetUsage) {
+        self.usage = usage;
+        let moved = self.render_world_mesh.0.get_mut().unwrap();
+        match (usage, &*moved) {
+            (PointCloudAssetUsage::RenderWorld, MovedMesh::Kept) => {
+                let topology = self.mesh.primitive_topology();
+                let mesh = std::mem::replace(&mut self.mesh, Mesh::new(topology));
+                *moved = MovedMesh::Pending(Box::new(mesh));
+            }
+            (
+                PointCloudAssetUsage::MainAndRenderWorld,
+                MovedMesh::Pending(_) | MovedMesh::Extracted,
+            ) => {
+                if let MovedMesh::Pending(mesh) = std::mem::take(moved) {
+                    self.mesh = *mesh;
+                }
+                self.changed_points.insert_unrecorded();
+            }
+            _ => {}
+        }
+    }
+
+    /// The points, edited with [`Self::mesh_mut`], [`Self::edit_points`] or the setters like
+    /// [`Self::set_positions`] so the changes are uploaded.
+    pub fn mesh(&self) -> &Mesh {
+        &self.mesh
+    }
+
+    /// Mutable access to the mesh, which uploads the whole asset again. Use
+    /// [`Self::edit_points`] or the setters like [`Self::set_positions`] to only upload the
+    /// edited points.
+    pub fn mesh_mut(&mut self) -> &mut Mesh {
+        self.changed_points.insert_unrecorded();
+        &mut self.mesh
+    }
+
+    /// Mutable access to the mesh to edit the points in `range`, so only they are uploaded
+    /// again. Edits of other points may not be uploaded.
+    pub fn edit_points(&mut self, range: Range<u32>) -> &mut Mesh {
+        self.changed_points.insert(range);
+        &mut self.mesh
+    }
+
+    /// Moves the points from index `start` on, uploading only them again.
+    pub fn set_positions(&mut self, start: u32, positions: &[Vec3]) {
+        self.set_attribute(Mesh::ATTRIBUTE_POSITION.id, start, positions);
+    }
+
+    /// Recolours the points from index `start` on, in linear RGB, uploading only them again.
+    /// Does nothing if the mesh has no colours.
+    pub fn set_colors(&mut self, start: u32, colors: &[Vec3]) {
+        self.set_attribute(ATTRIBUTE_COLOR.id, start, colors);
+    }
+
+    /// Changes the opacity of the points from index `start` on, uploading only them again.
+    /// Does nothing if the mesh has no [`ATTRIBUTE_ALPHA`].
+    pub fn set_alphas(&mut self, start: u32, alphas: &[f32]) {
+        let Some(VertexAttributeValues::Float32(attribute)) =
+            self.mesh.attribute_mut(ATTRIBUTE_ALPHA)
+        else {
+            return;
+        };
+        let start = (start as usize).min(attribute.len());
+        let end = (start + alphas.len()).min(attribute.len());
+        attribute[start..end].copy_from_slice(&alphas[..end - start]);
+        self.changed_points.insert(start as u32..end as u32);
+    }
+
+    fn set_attribute(&mut self, id: MeshVertexAttributeId, start: u32, values: &[Vec3]) {
+        let Some(VertexAttributeValues::Float32x3(attribute)) = self.mesh.attribute_mut(id) else {
+            return;
+        };
+        let start = (start as usize).min(attribute.len());
+        let end = (start + values.len()).min(attribute.len());
+        for (value, new_value) in attribute[start..end].iter_mut().zip(values) {
+            *value = new_value.to_array();
+        }
+        self.changed_points.insert(start as u32..end as u32);
+    }
+
+    /// Removes the points at `indices`, moving the last points into their place like
+    /// [`Vec::swap_remove`], so only the filled holes are uploaded again. This changes the
+    /// indices of the moved points, call
+    /// [`PointSelection::remove_points`](crate::PointSelection::remove_points) with the same
+    /// indices on the selections of the point cloud.
+    pub fn remove_points(&mut self, indices: impl IntoIterator<Item = u32>) {
+        let mut num_points = self.mesh.count_vertices();
+        let mut indices: Vec<usize> = indices
+            .into_iter()
+            .map(|index| index as usize)
+            .filter(|&index| index < num_points)
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+        // From the last index, so the moved points are never removed afterwards.
+        for &index in indices.iter().rev() {
+            if let Some(animation) = &mut self.animation {
+                let positions = match self.mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+                    Some(VertexAttributeValues::Float32x3(positions)) => positions.as_slice(),
+                    _ => &[],
+                };
+                let colors = match self.mesh.attribute(ATTRIBUTE_COLOR) {
+                    Some(VertexAttributeValues::Float32x3(colors)) => Some(colors.as_slice()),
+                    _ => None,
+                };
+                animation.swap_remove_point(index, positions, colors);
+            }
+            for (_, values) in self.mesh.attributes_mut() {
+                swap_remove(values, index);
+            }
+            num_points -= 1;
+            if index < num_points {
+                self.changed_points.insert(index as u32..index as u32 + 1);
+            }
         }
     }
 }
 
+/// Removes the value at `index`, replacing it by the last value.
+fn swap_remove(values: &mut VertexAttributeValues, index: usize) {
+    macro_rules! swap_remove {
+        ($($format:ident),*) => {
+            match values {
+                $(VertexAttributeValues::$format(values) => {
+                    values.swap_remove(index);
+                })*
+            }
+        };
+    }
+    swap_remove!(
+        Float32, Sint32, Uint32, Float32x2, Sint32x2, Uint32x2, Float32x3, Sint32x3, Uint32x3,
+        Float32x4, Sint32x4, Uint32x4, Sint16x2, Snorm16x2, Uint16x2, Unorm16x2, Sint16x4,
+        Snorm16x4, Uint16x4, Unorm16x4, Sint8x2, Snorm8x2, Uint8x2, Unorm8x2, Sint8x4, Snorm8x4,
+        Uint8x4, Unorm8x4
+    );
+}
+
 /// Possible errors that can be produced by [`LasLoader`]
 #[non_exhaustive]
 #[derive(Debug, Error)]
@@ -137,12 +472,135 @@ impl AssetLoader for LasLoader {
             }
             mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
             mesh.insert_attribute(ATTRIBUTE_COLOR, colors);
-            let asset = PointCloudAsset {
-                mesh,
-                animation: None,
-                animation_scale: Vec3::default(),
-            };
-            Ok(asset)
+            Ok(PointCloudAsset::new(mesh))
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asset(num_points: usize) -> PointCloudAsset {
+        let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            (0..num_points)
+                .map(|i| [i as f32, 0.0, 0.0])
+                .collect::<Vec<_>>(),
+        );
+        mesh.insert_attribute(
+            ATTRIBUTE_COLOR,
+            (0..num_points)
+                .map(|i| [0.0, i as f32, 0.0])
+                .collect::<Vec<_>>(),
+        );
+        PointCloudAsset::new(mesh)
+    }
+
+    fn positions(asset: &PointCloudAsset) -> Vec<f32> {
+        match asset.mesh().attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) => {
+                positions.iter().map(|position| position[0]).collect()
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn colors(asset: &PointCloudAsset) -> Vec<f32> {
+        match asset.mesh().attribute(ATTRIBUTE_COLOR) {
+            Some(VertexAttributeValues::Float32x3(colors)) => {
+                colors.iter().map(|color| color[1]).collect()
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn changed_points_merge_overlapping_ranges() {
+        let mut changed = ChangedPoints::default();
+        changed.insert(2..4);
+        changed.insert(4..6);
+        changed.insert(3..5);
+        changed.insert(5..5);
+        changed.insert(0..1);
+        assert_eq!(changed.take(), Some(vec![2..6, 0..1]));
+        assert_eq!(changed.take(), Some(vec![]));
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn unrecorded_edits_upload_everything() {
+        let mut asset = asset(4);
+        asset.set_positions(1, &[Vec3::ONE]);
+        asset.mesh_mut();
+        asset.set_colors(2, &[Vec3::ONE]);
+        assert_eq!(asset.changed_points.take(), None);
+
+        // The next edits are recorded again.
+        asset.edit_points(0..2);
+        assert_eq!(asset.changed_points.take(), Some(vec![0..2]));
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn setters_clamp_to_the_points() {
+        let mut asset = asset(4);
+        asset.set_positions(3, &[Vec3::splat(7.0), Vec3::splat(8.0)]);
+        asset.set_colors(9, &[Vec3::ONE]);
+        assert_eq!(positions(&asset), [0.0, 1.0, 2.0, 7.0]);
+        assert_eq!(colors(&asset), [0.0, 1.0, 2.0, 3.0]);
+        assert_eq!(asset.changed_points.take(), Some(vec![3..4]));
+    }
+
+    #[test]
+    fn remove_points_ignores_duplicate_and_out_of_range_indices() {
+        let mut asset = asset(6);
+        asset.remove_points([4, 1, 1, 99, 4]);
+        assert_eq!(positions(&asset), [0.0, 5.0, 2.0, 3.0]);
+        assert_eq!(colors(&asset), [0.0, 5.0, 2.0, 3.0]);
+        // The holes filled by the moved points, from the last one.
+        assert_eq!(asset.changed_points.take(), Some(vec![4..5, 1..2]));
+    }
+
+    #[test]
+    fn remove_last_points_uploads_nothing_but_the_count() {
+        let mut asset = asset(4);
+        asset.remove_points([3, 2]);
+        assert_eq!(positions(&asset), [0.0, 1.0]);
+        assert_eq!(asset.changed_points.take(), Some(vec![]));
+    }
+
+    #[test]
+    fn render_world_usage_moves_the_mesh_out_until_extracted() {
+        let mut asset = asset(4);
+        asset.set_usage(PointCloudAssetUsage::RenderWorld);
+        assert_eq!(asset.mesh().count_vertices(), 0);
+        asset.set_usage(PointCloudAssetUsage::MainAndRenderWorld);
+        assert_eq!(positions(&asset), [0.0, 1.0, 2.0, 3.0]);
+
+        asset.set_usage(PointCloudAssetUsage::RenderWorld);
+        let extracted = asset.render_world_mesh.take().unwrap();
+        assert_eq!(extracted.count_vertices(), 4);
+        assert!(asset.render_world_mesh.take().is_none());
+        // Setting the usage again doesn't move out the empty mesh.
+        asset.set_usage(PointCloudAssetUsage::RenderWorld);
+        assert!(asset.render_world_mesh.take().is_none());
+    }
+
+    #[test]
+    fn remove_points_from_animation_frames_shorter_than_the_mesh() {
+        let mut asset = asset(5);
+        asset.animation = Some(PointCloudAnimation::from_frames([
+            crate::AnimationFrame::new(1.0, crate::FramePoints::Offsets(vec![Vec3::X; 2])),
+        ]));
+        asset.remove_points([0, 3]);
+        assert_eq!(positions(&asset), [4.0, 1.0, 2.0]);
+        let animation = asset.animation.as_ref().unwrap();
+        let crate::FramePoints::Offsets(offsets) = &animation.frames()[0].points else {
+            unreachable!();
+        };
+        // Point 4 had no offset, so it keeps none at its new index.
+        assert_eq!(offsets, &[Vec3::ZERO, Vec3::X, Vec3::ZERO]);
+    }
+}
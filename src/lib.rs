@@ -1,34 +1,70 @@
+mod animation;
+mod clipping_volumes;
 mod clippling_planes;
 #[cfg(feature = "las")]
 mod las_loader;
+mod lighting;
+#[cfg(feature = "measurement")]
+mod measurement;
+mod normals;
 #[cfg(feature = "opd")]
 mod opd_loader;
+mod picking;
 mod pipeline;
 mod playback;
 mod render;
 mod render_graph;
+mod selection;
+mod shadows;
+mod shape;
+mod spatial_index;
+mod splatting;
+mod streaming;
+pub use animation::{AnimationFrame, FramePoints, PointCloudAnimation, ScalarColorRamp};
 use bevy::{
     asset::load_internal_asset,
     core_pipeline::core_3d::CORE_3D,
     prelude::*,
     render::{
-        extract_component::UniformComponentPlugin,
-        extract_resource::ExtractResourcePlugin,
-        render_asset::RenderAssetPlugin,
+        extract_component::{ExtractComponentPlugin, UniformComponentPlugin},
+        render_asset::RenderAssets,
         render_graph::{RenderGraphApp, ViewNodeRunner},
         render_resource::{ShaderStage, SpecializedRenderPipelines},
         Render, RenderApp, RenderSet,
     },
 };
-pub use clippling_planes::{ClippingPlaneBundle, ClippingPlaneRange};
+pub use clipping_volumes::{
+    ClippingBox, ClippingCylinder, ClippingHalfSpace, ClippingMode, ClippingPolygon,
+    ClippingPolygonSpace, ClippingPolytope, ClippingSphere,
+};
+pub use clippling_planes::{
+    ClippingHighlight, ClippingPlaneBundle, ClippingPlaneRange, ClippingPlaneTargets,
+};
 #[cfg(feature = "las")]
 pub use las_loader::*;
+pub use lighting::PointCloudShading;
+#[cfg(feature = "measurement")]
+pub use measurement::*;
 #[cfg(feature = "opd")]
 pub use opd_loader::*;
+pub use picking::{
+    PointCloudHit, PointCloudPickEvent, PointCloudPickingCamera, PointCloudPickingNode,
+    PointCloudPickingTarget,
+};
 pub use pipeline::*;
 pub use playback::*;
 pub use render::*;
 pub use render_graph::*;
+pub use selection::{
+    points_in_viewport_polygon, points_in_viewport_rect, PointSelection, SelectionOp,
+};
+pub use shadows::PointCloudShadowCaster;
+pub use shape::PointShape;
+pub use spatial_index::{
+    PointCloudRayHit, PointCloudSpatialIndex, PointCloudSpatialIndices, PointCloudSpatialQuery,
+};
+pub use splatting::{PointCloudRenderMode, SplatPass};
+pub use streaming::StreamingPointCloud;
 
 #[derive(Default)]
 pub struct PointCloudPlugin;
@@ -43,12 +79,30 @@ impl Plugin for PointCloudPlugin {
         app.init_asset_loader::<OpdLoader>();
 
         app.add_plugins((
-            RenderAssetPlugin::<PointCloudAsset>::default(),
             UniformComponentPlugin::<PointCloudUniform>::default(),
-            ExtractResourcePlugin::<PointCloudPlaybackControls>::default(),
+            ExtractComponentPlugin::<PlaybackControls>::default(),
+            ExtractComponentPlugin::<PointCloudShading>::default(),
+            ExtractComponentPlugin::<PointShape>::default(),
+            ExtractComponentPlugin::<PointCloudRenderMode>::default(),
+            ExtractComponentPlugin::<PointCloudShadowCaster>::default(),
         ))
-        .add_systems(PostUpdate, PointCloudPlaybackControls::playback_system)
-        .init_resource::<PointCloudPlaybackControls>();
+        .add_event::<PlaybackFinished>()
+        .add_event::<PlaybackLooped>()
+        .add_event::<PlaybackFrameEntered>()
+        .add_event::<PlaybackSeekOutOfRange>()
+        .add_systems(PostUpdate, PlaybackControls::playback_system);
+
+        let picking_readbacks = picking::PointCloudPickingReadbacks::default();
+        app.add_event::<PointCloudPickEvent>()
+            .add_systems(PreUpdate, picking::send_point_cloud_pick_events)
+            .insert_resource(picking_readbacks.clone());
+
+        app.init_resource::<PointCloudSpatialIndices>().add_systems(
+            PostUpdate,
+            spatial_index::PointCloudSpatialIndices::invalidate_system,
+        );
+
+        app.add_systems(First, StreamingPointCloud::expire_system);
 
         load_internal_asset!(
             app,
@@ -62,25 +116,58 @@ impl Plugin for PointCloudPlugin {
             "shader.frag",
             |s, path| { Shader::from_glsl(s, ShaderStage::Fragment, path) }
         );
+        load_internal_asset!(
+            app,
+            POINT_CLOUD_PICKING_FRAG_SHADER_HANDLE,
+            "picking.frag",
+            |s, path| { Shader::from_glsl(s, ShaderStage::Fragment, path) }
+        );
         load_internal_asset!(
             app,
             EYE_DOME_LIGHTING_SHADER_HANDLE,
             "eye-dome.wgsl",
             Shader::from_wgsl
         );
+        load_internal_asset!(
+            app,
+            POINT_CLOUD_SPLAT_SHADER_HANDLE,
+            "splat-normalize.wgsl",
+            Shader::from_wgsl
+        );
         let render_app = app.sub_app_mut(RenderApp);
 
         render_app
             .add_systems(
                 ExtractSchedule,
                 (
-                    extract_point_cloud,
                     clippling_planes::extract_clipping_planes,
-                ),
+                    clipping_volumes::extract_clipping_volumes,
+                    selection::extract_point_selections,
+                    lighting::extract_point_cloud_lights,
+                    extract_point_cloud_assets,
+                    extract_point_cloud,
+                    streaming::extract_streaming_point_clouds,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                ExtractSchedule,
+                picking::extract_point_cloud_picking_requests,
+            )
+            .add_systems(
+                Render,
+                (
+                    clippling_planes::prepare_clipping_planes,
+                    clipping_volumes::prepare_clipping_volumes,
+                    selection::prepare_point_selections,
+                    lighting::prepare_point_cloud_lights,
+                    streaming::prepare_streaming_point_clouds,
+                )
+                    .in_set(RenderSet::Prepare),
             )
             .add_systems(
                 Render,
-                (clippling_planes::prepare_clipping_planes,).in_set(RenderSet::Prepare),
+                prepare_point_cloud_assets.in_set(RenderSet::PrepareAssets),
             )
             .add_systems(
                 Render,
@@ -88,15 +175,38 @@ impl Plugin for PointCloudPlugin {
                     queue_point_cloud_bind_group,
                     queue_view_targets,
                     queue_point_cloud,
+                    picking::queue_picking_targets,
+                    splatting::queue_splat_view_targets,
                 )
                     .in_set(RenderSet::Queue),
             )
-            .init_resource::<clippling_planes::UniformBufferOfGpuClippingPlaneRanges>()
+            .add_systems(
+                Render,
+                picking::map_picking_readbacks
+                    .in_set(RenderSet::Cleanup)
+                    .before(World::clear_entities),
+            )
+            .insert_resource(picking_readbacks)
+            .init_resource::<picking::PointCloudPickingReadbackBuffers>()
+            .init_resource::<clippling_planes::StorageBufferOfGpuClippingPlaneRanges>()
+            .init_resource::<clipping_volumes::StorageBufferOfGpuClippingVolumes>()
+            .init_resource::<clipping_volumes::StorageBufferOfClippingElements>()
+            .init_resource::<clippling_planes::ClippingRanges>()
+            .init_resource::<selection::StorageBufferOfPointSelections>()
+            .init_resource::<selection::PointSelectionRanges>()
+            .init_resource::<lighting::StorageBufferOfGpuPointCloudLights>()
+            .init_resource::<shadows::PointCloudShadowLights>()
+            .init_resource::<shadows::PointCloudShadowMapBuffers>()
+            .init_resource::<shadows::PointCloudShadowCasters>()
+            .init_resource::<streaming::StreamingPointCloudBuffers>()
+            .init_resource::<RenderAssets<PointCloudAsset>>()
+            .init_resource::<ExtractedPointCloudAssets>()
+            .init_resource::<PrepareNextFramePointCloudAssets>()
             .init_resource::<PointCloudBindGroup>();
 
         render_app
             .add_systems(Render, prepare_animated_assets.in_set(RenderSet::Prepare))
-            .init_resource::<PointCloudPlaybackControls>();
+            .init_resource::<PointCloudAnimationInstances>();
 
         render_app
             .add_render_graph_node::<ViewNodeRunner<PointCloudNode>>(CORE_3D, PointCloudNode::NAME)
@@ -104,15 +214,52 @@ impl Plugin for PointCloudPlugin {
                 CORE_3D,
                 bevy::core_pipeline::core_3d::graph::node::END_MAIN_PASS,
                 PointCloudNode::NAME,
-            );
+            )
+            .add_render_graph_node::<ViewNodeRunner<PointCloudPickingNode>>(
+                CORE_3D,
+                PointCloudPickingNode::NAME,
+            )
+            .add_render_graph_edge(CORE_3D, PointCloudNode::NAME, PointCloudPickingNode::NAME);
     }
 
     fn finish(&self, app: &mut App) {
+        // Point clouds only cast and receive shadows with Bevy's shadow maps, which need the
+        // `PbrPlugin`. Checked here, as it may be added after this plugin.
+        let shadows = app.is_plugin_added::<bevy::pbr::PbrPlugin>();
         let render_app = app.sub_app_mut(RenderApp);
+        if shadows {
+            render_app
+                .add_systems(
+                    Render,
+                    shadows::prepare_point_cloud_shadow_maps.in_set(RenderSet::Prepare),
+                )
+                .add_systems(
+                    Render,
+                    (
+                        shadows::queue_point_cloud_shadow_bind_groups,
+                        shadows::queue_point_cloud_shadow_casters,
+                    )
+                        .in_set(RenderSet::Queue),
+                )
+                .add_render_graph_node::<ViewNodeRunner<shadows::PointCloudShadowNode>>(
+                    CORE_3D,
+                    shadows::PointCloudShadowNode::NAME,
+                )
+                .add_render_graph_edges(
+                    CORE_3D,
+                    &[
+                        bevy::pbr::draw_3d_graph::node::SHADOW_PASS,
+                        shadows::PointCloudShadowNode::NAME,
+                        bevy::core_pipeline::core_3d::graph::node::START_MAIN_PASS,
+                    ],
+                );
+        }
         render_app
             .init_resource::<PointCloudPipeline>()
             .init_resource::<SpecializedRenderPipelines<PointCloudPipeline>>()
             .init_resource::<EyeDomePipeline>()
-            .init_resource::<SpecializedRenderPipelines<EyeDomePipeline>>();
+            .init_resource::<SpecializedRenderPipelines<EyeDomePipeline>>()
+            .init_resource::<splatting::SplatNormalizePipeline>()
+            .init_resource::<SpecializedRenderPipelines<splatting::SplatNormalizePipeline>>();
     }
 }
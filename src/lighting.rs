@@ -0,0 +1,173 @@
+use bevy::{
+    ecs::query::QueryItem,
+    prelude::*,
+    render::{
+        extract_component::ExtractComponent,
+        render_resource::{ShaderType, StorageBuffer},
+        renderer::{RenderDevice, RenderQueue},
+        Extract,
+    },
+};
+
+use crate::shadows::{PointCloudShadowLight, PointCloudShadowLights};
+use crate::PotreePointCloud;
+
+/// The exposure Bevy's PBR shaders apply to directional lights, hard coded to an aperture of
+/// f/4, a shutter speed of 1/250 s and ISO 100.
+const DIRECTIONAL_LIGHT_EXPOSURE: f32 = 1.0 / (4000.0 * 1.2);
+
+/// How a [`PotreePointCloud`] is shaded.
+#[derive(Clone, Copy, Component, Debug, Default, PartialEq, Eq)]
+pub enum PointCloudShading {
+    /// Points show their colour, with only eye dome lighting for shape cues.
+    #[default]
+    Unlit,
+    /// Points are lit like a diffuse material by the [`DirectionalLight`]s, [`PointLight`]s,
+    /// [`SpotLight`]s and [`AmbientLight`] of the scene, and shadowed by the directional and
+    /// spot lights with shadows enabled. Needs the normals of
+    /// [`PointCloudAsset::estimate_normals`](crate::PointCloudAsset::estimate_normals), point
+    /// clouds without normals stay unlit.
+    Lit,
+}
+
+impl ExtractComponent for PointCloudShading {
+    type Query = &'static Self;
+    type Filter = With<PotreePointCloud>;
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::Query>) -> Option<Self> {
+        Some(*item)
+    }
+}
+
+#[derive(Clone, Debug, Default, ShaderType)]
+pub(crate) struct GpuPointCloudLight {
+    /// The direction towards a directional light with a `w` of zero, or the position of a
+    /// point light with a `w` of one.
+    pub position: Vec4,
+    /// The colour, premultiplied by the intensity, and `1 / range²` of point lights.
+    pub color_inverse_square_range: Vec4,
+    /// The direction a spot light points to.
+    pub spot_direction: Vec3,
+    /// Map the cosine of the angle to [`Self::spot_direction`] to the attenuation of the cone,
+    /// zero and one for lights without a cone.
+    pub spot_scale: f32,
+    pub spot_offset: f32,
+}
+
+#[derive(Clone, Debug, Default, ShaderType)]
+pub(crate) struct GpuPointCloudLights {
+    pub ambient_color: Vec4,
+    #[size(runtime)]
+    pub lights: Vec<GpuPointCloudLight>,
+}
+
+#[derive(Resource, Default)]
+pub struct StorageBufferOfGpuPointCloudLights(pub(crate) StorageBuffer<GpuPointCloudLights>);
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn extract_point_cloud_lights(
+    directional_lights: Extract<
+        Query<(
+            Entity,
+            &DirectionalLight,
+            &GlobalTransform,
+            &InheritedVisibility,
+        )>,
+    >,
+    point_lights: Extract<Query<(&PointLight, &GlobalTransform, &InheritedVisibility)>>,
+    spot_lights: Extract<Query<(Entity, &SpotLight, &GlobalTransform, &InheritedVisibility)>>,
+    ambient_light: Extract<Option<Res<AmbientLight>>>,
+    mut lights_buffer: ResMut<StorageBufferOfGpuPointCloudLights>,
+    mut shadow_lights: ResMut<PointCloudShadowLights>,
+) {
+    let gpu_lights = lights_buffer.0.get_mut();
+    gpu_lights.ambient_color = ambient_light.as_ref().map_or(Vec4::ZERO, |ambient_light| {
+        Vec4::from(ambient_light.color.as_linear_rgba_f32()) * ambient_light.brightness
+    });
+    gpu_lights.lights.clear();
+    shadow_lights.0.clear();
+    // Premultiplied like Bevy does, so point clouds match the lit meshes around them.
+    for (entity, light, transform, visibility) in &directional_lights {
+        if !visibility.get() {
+            continue;
+        }
+        if light.shadows_enabled {
+            shadow_lights.0.insert(
+                entity,
+                PointCloudShadowLight {
+                    index: gpu_lights.lights.len() as u32,
+                    depth_bias: light.shadow_depth_bias,
+                    // The factor of SQRT_2 is for the worst-case diagonal offset, like Bevy.
+                    normal_bias: light.shadow_normal_bias * std::f32::consts::SQRT_2,
+                },
+            );
+        }
+        gpu_lights.lights.push(GpuPointCloudLight {
+            position: transform.back().extend(0.0),
+            color_inverse_square_range: Vec4::from(light.color.as_linear_rgba_f32())
+                * light.illuminance
+                * DIRECTIONAL_LIGHT_EXPOSURE,
+            ..without_cone()
+        });
+    }
+    gpu_lights.lights.extend(
+        point_lights
+            .iter()
+            .filter(|(_, _, visibility)| visibility.get())
+            .map(|(light, transform, _)| GpuPointCloudLight {
+                position: transform.translation().extend(1.0),
+                color_inverse_square_range: luminous_intensity(light.color, light.intensity)
+                    .extend(1.0 / (light.range * light.range)),
+                ..without_cone()
+            }),
+    );
+    for (entity, light, transform, visibility) in &spot_lights {
+        if !visibility.get() {
+            continue;
+        }
+        if light.shadows_enabled {
+            shadow_lights.0.insert(
+                entity,
+                PointCloudShadowLight {
+                    index: gpu_lights.lights.len() as u32,
+                    depth_bias: light.shadow_depth_bias,
+                    normal_bias: light.shadow_normal_bias * std::f32::consts::SQRT_2,
+                },
+            );
+        }
+        // Like Bevy's spot lights, smoothly fading out between the inner and outer angles.
+        let cos_outer = light.outer_angle.cos();
+        let spot_scale = 1.0 / (light.inner_angle.cos() - cos_outer).max(1e-4);
+        gpu_lights.lights.push(GpuPointCloudLight {
+            position: transform.translation().extend(1.0),
+            color_inverse_square_range: luminous_intensity(light.color, light.intensity)
+                .extend(1.0 / (light.range * light.range)),
+            spot_direction: transform.forward(),
+            spot_scale,
+            spot_offset: -cos_outer * spot_scale,
+        });
+    }
+}
+
+/// From luminous power in lumens to luminous intensity in lumens per steradian, for point and
+/// spot lights alike.
+fn luminous_intensity(color: Color, intensity: f32) -> Vec3 {
+    Vec4::from(color.as_linear_rgba_f32()).truncate() * intensity / (4.0 * std::f32::consts::PI)
+}
+
+fn without_cone() -> GpuPointCloudLight {
+    GpuPointCloudLight {
+        spot_offset: 1.0,
+        ..default()
+    }
+}
+
+pub(crate) fn prepare_point_cloud_lights(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut lights_buffer: ResMut<StorageBufferOfGpuPointCloudLights>,
+) {
+    // Values already pushed in extract stage.
+    lights_buffer.0.write_buffer(&render_device, &render_queue);
+}
@@ -0,0 +1,337 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{
+    selection::is_inside_polygon, PointCloudAsset, PointCloudSpatialIndices,
+    PointCloudSpatialQuery, PotreePointCloud,
+};
+
+/// Computes the [`MeasurementValue`] of [`Measurement`] entities, and draws them with gizmos.
+#[derive(Default)]
+pub struct PointCloudMeasurementPlugin;
+
+impl Plugin for PointCloudMeasurementPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            (
+                update_measurements.after(PointCloudSpatialIndices::invalidate_system),
+                draw_measurements,
+            )
+                .after(bevy::transform::TransformSystem::TransformPropagate),
+        );
+    }
+}
+
+/// What a [`Measurement`] computes from its points. Heights are measured along the world Y axis.
+#[derive(Clone, Debug)]
+pub enum MeasurementKind {
+    /// The length of the polyline through the points.
+    Distance,
+    /// The area of the polygon through the points.
+    Area,
+    /// The volume of the point clouds above and below a horizontal base plane, within the
+    /// horizontal footprint of the polygon through the points, like a stockpile.
+    Volume {
+        /// The height of the base plane, the lowest point of the polygon if `None`.
+        base_height: Option<f32>,
+        /// The size of the grid cells the surface is sampled with. Each cell takes the height
+        /// of its highest point.
+        cell_size: f32,
+    },
+    /// The heights of the points within `width` of the polyline through the points,
+    /// like a cross section.
+    Profile { width: f32 },
+}
+
+/// A measurement vertex, possibly snapped to a point of a [`PotreePointCloud`].
+#[derive(Clone, Copy, Debug)]
+pub struct MeasurementPoint {
+    pub position: Vec3,
+    /// The point cloud entity and point index this vertex was snapped to.
+    pub snapped_to: Option<(Entity, u32)>,
+}
+
+impl From<Vec3> for MeasurementPoint {
+    fn from(position: Vec3) -> Self {
+        Self {
+            position,
+            snapped_to: None,
+        }
+    }
+}
+
+/// A persistent measurement, whose [`MeasurementValue`] is computed again whenever it changes.
+///
+/// Volumes and profiles use the points of every [`PotreePointCloud`] in the world, and are
+/// also computed again when a point cloud is added, moved, removed, or its asset changes.
+#[derive(Clone, Component, Debug)]
+pub struct Measurement {
+    pub kind: MeasurementKind,
+    pub points: Vec<MeasurementPoint>,
+    /// The color of the gizmo lines, or `None` to not draw them.
+    pub color: Option<Color>,
+}
+
+impl Measurement {
+    pub fn new(kind: MeasurementKind) -> Self {
+        Self {
+            kind,
+            points: Vec::new(),
+            color: Some(Color::YELLOW),
+        }
+    }
+
+    /// Adds a vertex at the point cloud point nearest to `position`, or at `position` itself if
+    /// there is no point within `snap_distance`.
+    pub fn push_snapped(
+        &mut self,
+        spatial_query: &mut PointCloudSpatialQuery,
+        position: Vec3,
+        snap_distance: f32,
+    ) {
+        let point = match spatial_query.nearest_point(position, snap_distance) {
+            Some(hit) => MeasurementPoint {
+                position: hit.position,
+                snapped_to: Some((hit.point_cloud, hit.point_index)),
+            },
+            None => position.into(),
+        };
+        self.points.push(point);
+    }
+
+    fn positions(&self) -> impl Iterator<Item = Vec3> + '_ {
+        self.points.iter().map(|point| point.position)
+    }
+}
+
+/// The values computed for a [`Measurement`], inserted on the same entity.
+#[derive(Clone, Component, Debug)]
+pub enum MeasurementValue {
+    Distance {
+        length: f32,
+        /// The length of the polyline projected on the horizontal plane.
+        horizontal_length: f32,
+        /// The height of the last point above the first one.
+        height_difference: f32,
+    },
+    Area {
+        /// The area of the polygon, assuming it is planar.
+        area: f32,
+        /// The area of the polygon projected on the horizontal plane.
+        horizontal_area: f32,
+        perimeter: f32,
+    },
+    Volume {
+        /// The volume of the surface above the base plane.
+        fill: f32,
+        /// The volume between the base plane and the surface below it.
+        cut: f32,
+        base_height: f32,
+        /// The horizontal area of the cells that contain points.
+        covered_area: f32,
+    },
+    Profile {
+        /// `(distance along the polyline, height)` of each point, sorted by distance.
+        samples: Vec<Vec2>,
+    },
+}
+
+#[allow(clippy::type_complexity)]
+fn update_measurements(
+    mut commands: Commands,
+    measurements: Query<(Entity, Ref<Measurement>)>,
+    changed_point_clouds: Query<
+        (),
+        (
+            With<PotreePointCloud>,
+            Or<(Changed<PotreePointCloud>, Changed<GlobalTransform>)>,
+        ),
+    >,
+    mut removed_point_clouds: RemovedComponents<PotreePointCloud>,
+    mut asset_events: EventReader<AssetEvent<PointCloudAsset>>,
+    mut spatial_query: PointCloudSpatialQuery,
+) {
+    // Any change of the point clouds may change the points within a measurement.
+    // Both readers are drained, so the events aren't seen again next frame.
+    let assets_changed = asset_events.read().count() > 0;
+    let point_clouds_removed = removed_point_clouds.read().count() > 0;
+    let point_clouds_changed =
+        assets_changed || point_clouds_removed || !changed_point_clouds.is_empty();
+    for (entity, measurement) in measurements.iter() {
+        let uses_points = matches!(
+            measurement.kind,
+            MeasurementKind::Volume { .. } | MeasurementKind::Profile { .. }
+        );
+        let recompute = measurement.is_changed() || (uses_points && point_clouds_changed);
+        if !recompute {
+            continue;
+        }
+        let value = match measurement.kind {
+            MeasurementKind::Distance => distance(&measurement),
+            MeasurementKind::Area => area(&measurement),
+            MeasurementKind::Volume {
+                base_height,
+                cell_size,
+            } => {
+                let points = footprint_points(&mut spatial_query, &measurement, 0.0);
+                volume(&measurement, base_height, cell_size, points)
+            }
+            MeasurementKind::Profile { width } => {
+                let points = footprint_points(&mut spatial_query, &measurement, width * 0.5);
+                profile(&measurement, width, points)
+            }
+        };
+        commands.entity(entity).insert(value);
+    }
+}
+
+/// The world space positions of the points within `margin` of the horizontal bounds of the
+/// measurement, at any height.
+fn footprint_points(
+    spatial_query: &mut PointCloudSpatialQuery,
+    measurement: &Measurement,
+    margin: f32,
+) -> impl Iterator<Item = Vec3> {
+    let (min, max) = measurement
+        .positions()
+        .fold((Vec2::INFINITY, Vec2::NEG_INFINITY), |(min, max), p| {
+            (min.min(p.xz()), max.max(p.xz()))
+        });
+    let points = if min.cmple(max).all() {
+        spatial_query.within_box(
+            Vec3::new(min.x - margin, f32::NEG_INFINITY, min.y - margin),
+            Vec3::new(max.x + margin, f32::INFINITY, max.y + margin),
+        )
+    } else {
+        Vec::new()
+    };
+    points.into_iter().map(|(_, _, position)| position)
+}
+
+fn distance(measurement: &Measurement) -> MeasurementValue {
+    let positions: Vec<_> = measurement.positions().collect();
+    let segments = positions.windows(2);
+    MeasurementValue::Distance {
+        length: segments.clone().map(|s| s[0].distance(s[1])).sum(),
+        horizontal_length: segments.map(|s| s[0].xz().distance(s[1].xz())).sum(),
+        height_difference: match (positions.first(), positions.last()) {
+            (Some(first), Some(last)) => last.y - first.y,
+            _ => 0.0,
+        },
+    }
+}
+
+fn area(measurement: &Measurement) -> MeasurementValue {
+    let positions: Vec<_> = measurement.positions().collect();
+    // A polygon needs at least three vertices.
+    let num_edges = if positions.len() > 2 {
+        positions.len()
+    } else {
+        0
+    };
+    let edges = || {
+        positions
+            .iter()
+            .zip(positions.iter().cycle().skip(1))
+            .take(num_edges)
+    };
+    // Newell's method, the length of the vector area is the area of a planar polygon.
+    let vector_area: Vec3 = edges().map(|(a, b)| a.cross(*b)).sum::<Vec3>() * 0.5;
+    MeasurementValue::Area {
+        area: vector_area.length(),
+        horizontal_area: vector_area.y.abs(),
+        perimeter: edges().map(|(a, b)| a.distance(*b)).sum(),
+    }
+}
+
+fn volume(
+    measurement: &Measurement,
+    base_height: Option<f32>,
+    cell_size: f32,
+    points: impl Iterator<Item = Vec3>,
+) -> MeasurementValue {
+    let footprint: Vec<Vec2> = measurement.positions().map(|p| p.xz()).collect();
+    let base_height = base_height.unwrap_or_else(|| {
+        measurement
+            .positions()
+            .map(|p| p.y)
+            .fold(f32::INFINITY, f32::min)
+    });
+    let mut cells: HashMap<IVec2, f32> = HashMap::default();
+    if footprint.len() > 2 && cell_size > 0.0 {
+        for point in points {
+            if is_inside_polygon(&footprint, point.xz()) {
+                let cell = (point.xz() / cell_size).floor().as_ivec2();
+                let height = cells.entry(cell).or_insert(f32::NEG_INFINITY);
+                *height = height.max(point.y);
+            }
+        }
+    }
+    let cell_area = cell_size * cell_size;
+    let (mut fill, mut cut) = (0.0, 0.0);
+    for &height in cells.values() {
+        let height = height - base_height;
+        if height > 0.0 {
+            fill += height * cell_area;
+        } else {
+            cut -= height * cell_area;
+        }
+    }
+    MeasurementValue::Volume {
+        fill,
+        cut,
+        base_height,
+        covered_area: cells.len() as f32 * cell_area,
+    }
+}
+
+fn profile(
+    measurement: &Measurement,
+    width: f32,
+    points: impl Iterator<Item = Vec3>,
+) -> MeasurementValue {
+    let line: Vec<Vec2> = measurement.positions().map(|p| p.xz()).collect();
+    let mut samples: Vec<Vec2> = points
+        .filter_map(|point| {
+            // Find the nearest segment of the polyline, on the horizontal plane.
+            let mut start_distance = 0.0;
+            let mut nearest: Option<(f32, f32)> = None;
+            for segment in line.windows(2) {
+                let (a, b) = (segment[0], segment[1]);
+                let length = a.distance(b);
+                let t = if length > 0.0 {
+                    ((point.xz() - a).dot(b - a) / (length * length)).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                let offset = point.xz().distance(a.lerp(b, t));
+                if offset <= width * 0.5 && !nearest.is_some_and(|(o, _)| offset >= o) {
+                    nearest = Some((offset, start_distance + t * length));
+                }
+                start_distance += length;
+            }
+            nearest.map(|(_, along)| Vec2::new(along, point.y))
+        })
+        .collect();
+    samples.sort_by(|a, b| a.x.total_cmp(&b.x));
+    MeasurementValue::Profile { samples }
+}
+
+fn draw_measurements(mut gizmos: Gizmos, measurements: Query<&Measurement>) {
+    for measurement in measurements.iter() {
+        let Some(color) = measurement.color else {
+            continue;
+        };
+        let closed = matches!(
+            measurement.kind,
+            MeasurementKind::Area | MeasurementKind::Volume { .. }
+        );
+        let first = measurement.points.first().filter(|_| closed);
+        gizmos.linestrip(
+            measurement
+                .positions()
+                .chain(first.map(|point| point.position)),
+            color,
+        );
+    }
+}
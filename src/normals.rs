@@ -0,0 +1,83 @@
+use bevy::{prelude::*, render::mesh::VertexAttributeValues};
+
+use crate::{PointCloudAsset, PointCloudSpatialIndex, ATTRIBUTE_NORMAL};
+
+impl PointCloudAsset {
+    /// Estimates the normal of each point from its `k` nearest neighbours, as the direction
+    /// they spread the least in, and stores them in [`ATTRIBUTE_NORMAL`] for
+    /// [`PointCloudShading::Lit`](crate::PointCloudShading::Lit).
+    ///
+    /// Normals may point to either side of the surface, lit points are shaded from the side
+    /// facing the camera. Points with fewer than three neighbours get a normal along `Y`.
+    pub fn estimate_normals(&mut self, k: usize) {
+        let Some(VertexAttributeValues::Float32x3(positions)) =
+            self.mesh().attribute(Mesh::ATTRIBUTE_POSITION)
+        else {
+            return;
+        };
+        let positions: Vec<Vec3> = positions.iter().copied().map(Vec3::from).collect();
+        let index = PointCloudSpatialIndex::new(positions.iter().copied());
+        let normals: Vec<[f32; 3]> = positions
+            .iter()
+            .map(|&position| {
+                let neighbours: Vec<Vec3> = index
+                    .nearest(&GlobalTransform::IDENTITY, position, k.max(3))
+                    .into_iter()
+                    .map(|(neighbour, _)| positions[neighbour as usize])
+                    .collect();
+                estimate_normal(&neighbours).to_array()
+            })
+            .collect();
+        self.mesh_mut().insert_attribute(ATTRIBUTE_NORMAL, normals);
+    }
+}
+
+/// The eigenvector of the smallest eigenvalue of the covariance of `points`.
+fn estimate_normal(points: &[Vec3]) -> Vec3 {
+    if points.len() < 3 {
+        return Vec3::Y;
+    }
+    let mean = points.iter().sum::<Vec3>() / points.len() as f32;
+    let mut covariance = Mat3::ZERO;
+    for point in points {
+        let d = *point - mean;
+        covariance += Mat3::from_cols(d * d.x, d * d.y, d * d.z);
+    }
+    let [c00, c11, c22] = [
+        covariance.x_axis.x,
+        covariance.y_axis.y,
+        covariance.z_axis.z,
+    ];
+    let [c01, c02, c12] = [
+        covariance.y_axis.x,
+        covariance.z_axis.x,
+        covariance.z_axis.y,
+    ];
+
+    // The eigenvalues of a symmetric 3x3 matrix, from the angle of its deviatoric part.
+    let q = (c00 + c11 + c22) / 3.0;
+    let p1 = c01 * c01 + c02 * c02 + c12 * c12;
+    let p2 = (c00 - q).powi(2) + (c11 - q).powi(2) + (c22 - q).powi(2) + 2.0 * p1;
+    let p = (p2 / 6.0).sqrt();
+    if p <= f32::EPSILON * q.abs().max(f32::MIN_POSITIVE) {
+        // Spread evenly, every direction is as good.
+        return Vec3::Y;
+    }
+    let b = (covariance - Mat3::from_diagonal(Vec3::splat(q))) * (1.0 / p);
+    let phi = (b.determinant() / 2.0).clamp(-1.0, 1.0).acos() / 3.0;
+    let smallest = q + 2.0 * p * (phi + 2.0 * std::f32::consts::FRAC_PI_3).cos();
+
+    // The eigenvector is orthogonal to the rows of `covariance - smallest * I`, take the
+    // most stable cross product of two of them.
+    let m = covariance - Mat3::from_diagonal(Vec3::splat(smallest));
+    let rows = [m.row(0), m.row(1), m.row(2)];
+    [
+        rows[0].cross(rows[1]),
+        rows[0].cross(rows[2]),
+        rows[1].cross(rows[2]),
+    ]
+    .into_iter()
+    .max_by(|a, b| a.length_squared().total_cmp(&b.length_squared()))
+    .and_then(Vec3::try_normalize)
+    .unwrap_or(Vec3::Y)
+}
@@ -1,4 +1,4 @@
-use crate::PointCloudAsset;
+use crate::{AnimationFrame, FramePoints, PointCloudAnimation, PointCloudAsset};
 use bevy::{
     asset::{io::Reader, AssetLoader, AsyncReadExt, LoadContext},
     math::Vec3A,
@@ -9,6 +9,7 @@ use bevy::{
         BoxedFuture,
     },
 };
+use opd_parser::Frames;
 
 #[derive(Default)]
 pub struct OpdLoader;
@@ -38,11 +39,33 @@ impl OpdLoader {
         let mut mesh = Mesh::new(PrimitiveTopology::PointList);
         mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
 
-        Ok(PointCloudAsset {
-            mesh,
-            animation: Some(file.frames),
-            animation_scale: file.header.directive.scale.into(),
-        })
+        let mut asset = PointCloudAsset::new(mesh);
+        asset.animation = Self::animation(&file.frames, file.header.directive.scale.into());
+        Ok(asset)
+    }
+
+    /// Converts OPD frames, with timestamps in milliseconds and offsets in units of `scale`.
+    /// The offsets stay quantized, so replays take as much memory as the file.
+    fn animation(frames: &Frames, scale: Vec3) -> Option<PointCloudAnimation> {
+        let Frames::I8(frames) = frames else {
+            warn!("Only OPD files with 8 bit offsets can be animated");
+            return None;
+        };
+        let frames = frames.iter().map(|frame| {
+            AnimationFrame::new(
+                frame.time / 1000.,
+                FramePoints::Quantized {
+                    // The components are fractions of `i8::MAX`.
+                    scale: scale / i8::MAX as f32,
+                    offsets: frame
+                        .data
+                        .chunks_exact(3)
+                        .map(|offset| [offset[0], offset[1], offset[2]])
+                        .collect(),
+                },
+            )
+        });
+        Some(PointCloudAnimation::from_frames(frames))
     }
 }
 
@@ -0,0 +1,69 @@
+#version 450
+
+#import bevy_render::view::View
+
+layout(location = 0) out uvec2 o_Id;
+layout(location = 1) out vec4 o_Position;
+layout(location = 0) in vec2 in_Point_Location;
+layout(location = 1) in vec3 in_Color;
+layout(location = 2) flat in uint in_Point_Index;
+layout(location = 3) flat in vec3 in_World_Position;
+
+layout(set = 0, binding = 0) uniform View view;
+layout(set = 2, binding = 0) uniform Model {
+    mat4 model_transform;
+    float point_size;
+    uint first_clipping_plane;
+    uint num_clipping_planes;
+    uint first_clipping_volume;
+    uint num_clipping_volumes;
+    uint first_selection_word;
+    uint num_selection_words;
+    vec4 selection_color;
+    vec2 scalar_range;
+    uint num_ramp_colors;
+    vec4 ramp_colors[8];
+    float stream_time;
+    float stream_max_age;
+    float stream_fade_duration;
+    uint stream_first;
+    uint stream_capacity;
+    float opacity;
+    uint picking_id;
+};
+
+void main()
+{
+    // Same shape and depth as shader.frag, so the picked point is the visible one.
+    vec2 uv = in_Point_Location * 2.0 - 1.0;
+    #ifdef CIRCLE
+    if (dot(uv, uv) > 1.0) {
+        discard;
+    }
+    #endif
+
+    #ifdef ORIENTED_DISK
+    float depth_offset = 0.0;
+    #else
+    #ifdef PARABOLOID
+    float depth_offset = dot(uv, uv);
+    #else
+    float depth_offset = sqrt(uv.x * uv.x + uv.y * uv.y);
+    #endif
+    #endif
+
+    float depth = 1.0 / gl_FragCoord.w; // the world space depth
+
+    if (view.projection[2][3] != -1.0) {
+        // orthographic projection
+        depth_offset *= view.projection[2][2];
+    }
+
+    float offseted_depth = depth + point_size * depth_offset;
+
+    float z_near = gl_FragCoord.z * depth;
+    gl_FragDepth = z_near / offseted_depth;
+
+    o_Id = uvec2(picking_id, in_Point_Index);
+    o_Position = vec4(in_World_Position, 1.0);
+}
@@ -0,0 +1,461 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+
+use bevy::{
+    ecs::query::QueryItem,
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        extract_component::DynamicUniformIndex,
+        mesh::VertexAttributeValues,
+        render_asset::RenderAssets,
+        render_graph::{NodeRunError, RenderGraphContext, ViewNode},
+        render_resource::{
+            Buffer, BufferDescriptor, BufferUsages, Extent3d, ImageCopyBuffer, ImageCopyTexture,
+            ImageDataLayout, LoadOp, MapMode, Operations, Origin3d, PipelineCache,
+            RenderPassColorAttachment, RenderPassDepthStencilAttachment, RenderPassDescriptor,
+            Texture, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat,
+            TextureUsages, TextureView,
+        },
+        renderer::{RenderContext, RenderDevice},
+        texture::TextureCache,
+        view::ViewUniformOffset,
+        Extract,
+    },
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    PointCloudAnimationInstances, PointCloudAsset, PointCloudBindGroup, PointCloudDrawList,
+    PointCloudPipeline, PointCloudUniform, PotreePointCloud, ATTRIBUTE_COLOR,
+};
+
+pub(crate) const POINT_CLOUD_PICKING_ID_FORMAT: TextureFormat = TextureFormat::Rg32Uint;
+pub(crate) const POINT_CLOUD_PICKING_POSITION_FORMAT: TextureFormat = TextureFormat::Rgba32Float;
+
+/// The id texel and the position texel are copied to separate rows of the readback buffer,
+/// which must be aligned to `wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`.
+const READBACK_ROW_SIZE: u64 = 256;
+
+/// Picks the point cloud point under a cursor on the GPU.
+///
+/// While `cursor` is set, the point clouds seen by this camera are rendered to an offscreen
+/// id buffer every frame, and a [`PointCloudPickEvent`] is sent a few frames later with the result.
+/// Cursors outside of the viewport, or cameras that draw no point cloud, pick nothing.
+#[derive(Clone, Component, Debug, Default)]
+pub struct PointCloudPickingCamera {
+    /// The cursor position, in logical viewport coordinates like [`Window::cursor_position`].
+    pub cursor: Option<Vec2>,
+}
+
+/// The result of a pick requested by a [`PointCloudPickingCamera`].
+#[derive(Clone, Debug, Event)]
+pub struct PointCloudPickEvent {
+    pub camera: Entity,
+    /// `None` if there is no point under the cursor.
+    pub hit: Option<PointCloudHit>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PointCloudHit {
+    /// The [`PotreePointCloud`] entity.
+    pub point_cloud: Entity,
+    /// The index of the point in the [`PointCloudAsset`].
+    pub point_index: u32,
+    /// The world space position of the point's centroid, including animation offsets.
+    pub position: Vec3,
+    /// The color attribute of the point, if the asset has one.
+    pub color: Option<Vec3>,
+}
+
+/// Picks read back in the render world, waiting to be sent as events in the main world.
+#[derive(Resource, Clone, Default)]
+pub(crate) struct PointCloudPickingReadbacks(Arc<Mutex<Vec<PointCloudPickEvent>>>);
+
+pub(crate) fn send_point_cloud_pick_events(
+    readbacks: Res<PointCloudPickingReadbacks>,
+    point_clouds: Query<&PotreePointCloud>,
+    assets: Res<Assets<PointCloudAsset>>,
+    mut events: EventWriter<PointCloudPickEvent>,
+) {
+    let picks = std::mem::take(&mut *readbacks.0.lock().unwrap());
+    events.send_batch(picks.into_iter().map(|mut pick| {
+        if let Some(hit) = &mut pick.hit {
+            hit.color = point_clouds
+                .get(hit.point_cloud)
+                .ok()
+                .and_then(|point_cloud| assets.get(&point_cloud.mesh))
+                .and_then(|asset| match asset.mesh().attribute(ATTRIBUTE_COLOR) {
+                    Some(VertexAttributeValues::Float32x3(colors)) => colors
+                        .get(hit.point_index as usize)
+                        .copied()
+                        .map(Vec3::from),
+                    _ => None,
+                });
+        }
+        pick
+    }));
+}
+
+#[derive(Component)]
+pub(crate) struct ExtractedPointCloudPickingRequest {
+    /// The physical pixel of the render target to pick, `None` outside of the viewport.
+    pub pixel: Option<UVec2>,
+}
+
+pub(crate) fn extract_point_cloud_picking_requests(
+    mut commands: Commands,
+    cameras: Extract<Query<(Entity, &Camera, &PointCloudPickingCamera)>>,
+) {
+    for (entity, camera, picking) in cameras.iter() {
+        let Some(cursor) = picking.cursor else {
+            continue;
+        };
+        let pixel = camera
+            .physical_viewport_rect()
+            .zip(camera.logical_viewport_size())
+            .map(|(rect, logical_size)| {
+                let scale = rect.size().as_vec2() / logical_size;
+                (rect, rect.min.as_vec2() + cursor * scale)
+            })
+            .filter(|(rect, pixel)| {
+                pixel.cmpge(rect.min.as_vec2()).all() && pixel.cmplt(rect.max.as_vec2()).all()
+            })
+            .map(|(_, pixel)| pixel.as_uvec2());
+        commands
+            .get_or_spawn(entity)
+            .insert(ExtractedPointCloudPickingRequest { pixel });
+    }
+}
+
+#[derive(Component)]
+pub struct PointCloudPickingTarget {
+    pub id_texture: Texture,
+    pub id_texture_view: TextureView,
+    pub position_texture: Texture,
+    pub position_texture_view: TextureView,
+    pub depth_texture_view: TextureView,
+    pub readback_buffer: Buffer,
+    /// Whether [`Self::readback_buffer`] is in use until its pick is read back.
+    pub(crate) readback_in_use: Arc<AtomicBool>,
+    pub pixel: UVec2,
+}
+
+/// The readback buffers of each picking camera, reused once their picks were read back.
+#[derive(Resource, Default)]
+pub(crate) struct PointCloudPickingReadbackBuffers(HashMap<Entity, Vec<(Buffer, Arc<AtomicBool>)>>);
+
+pub(crate) fn queue_picking_targets(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    mut texture_cache: ResMut<TextureCache>,
+    mut readback_buffers: ResMut<PointCloudPickingReadbackBuffers>,
+    cameras: Query<(Entity, &ExtractedCamera, &ExtractedPointCloudPickingRequest)>,
+) {
+    let cameras_picking: HashSet<Entity> = cameras.iter().map(|(entity, ..)| entity).collect();
+    readback_buffers
+        .0
+        .retain(|entity, _| cameras_picking.contains(entity));
+
+    for (entity, camera, request) in cameras.iter() {
+        let (Some(target_size), Some(pixel)) = (camera.physical_target_size, request.pixel) else {
+            continue;
+        };
+        let size = Extent3d {
+            width: target_size.x,
+            height: target_size.y,
+            depth_or_array_layers: 1,
+        };
+        let mut texture = |label, format, usage| {
+            texture_cache.get(
+                &render_device,
+                TextureDescriptor {
+                    label: Some(label),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format,
+                    usage,
+                    view_formats: &[],
+                },
+            )
+        };
+        let id_texture = texture(
+            "point_cloud_picking_id_texture",
+            POINT_CLOUD_PICKING_ID_FORMAT,
+            TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+        );
+        let position_texture = texture(
+            "point_cloud_picking_position_texture",
+            POINT_CLOUD_PICKING_POSITION_FORMAT,
+            TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+        );
+        let depth_texture = texture(
+            "point_cloud_picking_depth_texture",
+            TextureFormat::Depth32Float,
+            TextureUsages::RENDER_ATTACHMENT,
+        );
+        // The buffers of the previous frames may still be mapped.
+        let buffers = readback_buffers.0.entry(entity).or_default();
+        let free_buffer = buffers
+            .iter()
+            .find(|(_, in_use)| !in_use.load(Ordering::Acquire));
+        let (readback_buffer, readback_in_use) = match free_buffer {
+            Some(buffer) => buffer.clone(),
+            None => {
+                let buffer = render_device.create_buffer(&BufferDescriptor {
+                    label: Some("point_cloud_picking_readback_buffer"),
+                    size: READBACK_ROW_SIZE * 2,
+                    usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                    mapped_at_creation: false,
+                });
+                buffers.push((buffer, Arc::default()));
+                buffers.last().unwrap().clone()
+            }
+        };
+        readback_in_use.store(true, Ordering::Release);
+
+        commands.entity(entity).insert(PointCloudPickingTarget {
+            id_texture: id_texture.texture,
+            id_texture_view: id_texture.default_view,
+            position_texture: position_texture.texture,
+            position_texture_view: position_texture.default_view,
+            depth_texture_view: depth_texture.default_view,
+            readback_buffer,
+            readback_in_use,
+            pixel: pixel.min(target_size - 1),
+        });
+    }
+}
+
+/// Renders point ids for views with a [`PointCloudPickingTarget`], and copies the picked
+/// pixel to its readback buffer.
+pub struct PointCloudPickingNode {
+    entity_query: QueryState<(
+        &'static Handle<PointCloudAsset>,
+        &'static DynamicUniformIndex<PointCloudUniform>,
+    )>,
+}
+
+impl PointCloudPickingNode {
+    pub const NAME: &'static str = "point_cloud_picking_node";
+}
+
+impl FromWorld for PointCloudPickingNode {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            entity_query: world.query_filtered(),
+        }
+    }
+}
+
+impl ViewNode for PointCloudPickingNode {
+    type ViewQuery = (
+        &'static ExtractedCamera,
+        &'static ViewUniformOffset,
+        &'static PointCloudPickingTarget,
+        &'static PointCloudDrawList,
+    );
+
+    fn update(&mut self, world: &mut World) {
+        self.entity_query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        (camera, view_uniform_offset, target, draw_list): QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let point_cloud_pipeline = world.resource::<PointCloudPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let render_assets = world.resource::<RenderAssets<PointCloudAsset>>();
+        let animation_instances = world.resource::<PointCloudAnimationInstances>();
+
+        let bind_groups = world.resource::<PointCloudBindGroup>();
+
+        // Cleared and read back even if nothing is drawn, the readback buffers are reused.
+        let mut tracked_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("point_cloud_picking"),
+            color_attachments: &[
+                Some(RenderPassColorAttachment {
+                    view: &target.id_texture_view,
+                    resolve_target: None,
+                    ops: Operations {
+                        // Point cloud id 0 means no hit.
+                        load: LoadOp::Clear(Color::NONE.into()),
+                        store: true,
+                    },
+                }),
+                Some(RenderPassColorAttachment {
+                    view: &target.position_texture_view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::NONE.into()),
+                        store: true,
+                    },
+                }),
+            ],
+            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                view: &target.depth_texture_view,
+                depth_ops: Some(Operations {
+                    // NOTE: 0.0 is the far plane due to bevy's use of reverse-z projections.
+                    load: LoadOp::Clear(0.0),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        if let Some(viewport) = camera.viewport.as_ref() {
+            tracked_pass.set_camera_viewport(viewport);
+        }
+        // Only the picked pixel is read back.
+        tracked_pass.set_scissor_rect(target.pixel.x, target.pixel.y, 1, 1);
+
+        if let (Some(bind_group), Some(model_bind_group)) = (
+            bind_groups.bind_group.as_ref(),
+            bind_groups.model_bind_group.as_ref(),
+        ) {
+            tracked_pass.set_bind_group(0, bind_group, &[view_uniform_offset.offset]);
+            tracked_pass
+                .set_vertex_buffer(0, point_cloud_pipeline.instanced_point_quad.slice(0..32));
+            for draw_data in &draw_list.list {
+                let Some(pipeline) = draw_data
+                    .picking_pipeline_id
+                    .and_then(|id| pipeline_cache.get_render_pipeline(id))
+                else {
+                    continue;
+                };
+                let Ok((point_cloud_asset, dynamic_index)) =
+                    self.entity_query.get_manual(world, draw_data.entity)
+                else {
+                    continue;
+                };
+                let Some(point_cloud_asset) = render_assets.get(point_cloud_asset) else {
+                    continue;
+                };
+                let Some(point_cloud_bind_group) =
+                    animation_instances.bind_group(draw_data.entity, point_cloud_asset)
+                else {
+                    continue;
+                };
+
+                tracked_pass.set_render_pipeline(pipeline);
+                tracked_pass.set_bind_group(1, point_cloud_bind_group, &[]);
+                tracked_pass.set_bind_group(2, model_bind_group, &[dynamic_index.index()]);
+                tracked_pass.draw(0..4, 0..point_cloud_asset.num_points);
+            }
+        }
+        drop(tracked_pass);
+
+        for (row, texture) in [&target.id_texture, &target.position_texture]
+            .into_iter()
+            .enumerate()
+        {
+            render_context.command_encoder().copy_texture_to_buffer(
+                ImageCopyTexture {
+                    texture,
+                    mip_level: 0,
+                    origin: Origin3d {
+                        x: target.pixel.x,
+                        y: target.pixel.y,
+                        z: 0,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                ImageCopyBuffer {
+                    buffer: &target.readback_buffer,
+                    layout: ImageDataLayout {
+                        offset: row as u64 * READBACK_ROW_SIZE,
+                        bytes_per_row: None,
+                        rows_per_image: None,
+                    },
+                },
+                Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Maps the readback buffers once the frame is submitted, the results are sent to the
+/// main world when the GPU is done with them. Requests that weren't drawn miss right away.
+#[allow(clippy::type_complexity)]
+pub(crate) fn map_picking_readbacks(
+    requests: Query<
+        (
+            Entity,
+            Option<&PointCloudPickingTarget>,
+            Option<&PointCloudDrawList>,
+        ),
+        With<ExtractedPointCloudPickingRequest>,
+    >,
+    uniforms: Query<&PointCloudUniform>,
+    readbacks: Res<PointCloudPickingReadbacks>,
+) {
+    for (camera, target, draw_list) in requests.iter() {
+        let (Some(target), Some(draw_list)) = (target, draw_list) else {
+            if let Some(target) = target {
+                target.readback_in_use.store(false, Ordering::Release);
+            }
+            readbacks
+                .0
+                .lock()
+                .unwrap()
+                .push(PointCloudPickEvent { camera, hit: None });
+            continue;
+        };
+        let buffer = target.readback_buffer.clone();
+        let in_use = target.readback_in_use.clone();
+        let point_clouds: Vec<(u32, Entity)> = draw_list
+            .list
+            .iter()
+            .filter_map(|d| Some((uniforms.get(d.entity).ok()?.picking_id, d.entity)))
+            .collect();
+        let readbacks = readbacks.clone();
+        // The polling for this map call is done every frame when the command queue is submitted.
+        target
+            .readback_buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                if let Err(error) = result {
+                    warn!("Failed to read back point cloud picking buffer: {error}");
+                    in_use.store(false, Ordering::Release);
+                    return;
+                }
+                let hit = {
+                    let data = buffer.slice(..).get_mapped_range();
+                    let id: [u32; 2] =
+                        bytemuck::pod_read_unaligned(&data[..std::mem::size_of::<[u32; 2]>()]);
+                    let position: [f32; 4] = bytemuck::pod_read_unaligned(
+                        &data[READBACK_ROW_SIZE as usize
+                            ..READBACK_ROW_SIZE as usize + std::mem::size_of::<[f32; 4]>()],
+                    );
+                    point_clouds
+                        .iter()
+                        .find(|&&(picking_id, _)| picking_id == id[0])
+                        .map(|&(_, point_cloud)| PointCloudHit {
+                            point_cloud,
+                            point_index: id[1],
+                            position: Vec4::from(position).truncate(),
+                            color: None,
+                        })
+                };
+                buffer.unmap();
+                in_use.store(false, Ordering::Release);
+                readbacks
+                    .0
+                    .lock()
+                    .unwrap()
+                    .push(PointCloudPickEvent { camera, hit });
+            });
+    }
+}
@@ -1,9 +1,10 @@
 use bevy::{
+    ecs::query::QueryItem,
     prelude::*,
     render::{
         camera::ExtractedCamera,
         extract_component::ComponentUniforms,
-        extract_resource::ExtractResource,
+        extract_component::ExtractComponent,
         render_asset::RenderAssets,
         render_resource::*,
         renderer::{RenderDevice, RenderQueue},
@@ -14,8 +15,14 @@ use bevy::{
 };
 
 use crate::{
-    clippling_planes::UniformBufferOfGpuClippingPlaneRanges, PointCloudAsset,
-    PointCloudPlaybackControls, PointCloudUniform,
+    clipping_volumes::{StorageBufferOfClippingElements, StorageBufferOfGpuClippingVolumes},
+    clippling_planes::StorageBufferOfGpuClippingPlaneRanges,
+    lighting::StorageBufferOfGpuPointCloudLights,
+    picking::{POINT_CLOUD_PICKING_ID_FORMAT, POINT_CLOUD_PICKING_POSITION_FORMAT},
+    selection::StorageBufferOfPointSelections,
+    splatting::{SplatPass, SPLAT_ACCUMULATION_FORMAT},
+    PlaybackControls, PointCloudAnimationInstance, PointCloudAnimationInstances, PointCloudAsset,
+    PointCloudUniform, PointShape, PotreePointCloud,
 };
 
 pub(crate) const POINT_CLOUD_VERT_SHADER_HANDLE: Handle<Shader> =
@@ -24,21 +31,58 @@ pub(crate) const POINT_CLOUD_FRAG_SHADER_HANDLE: Handle<Shader> =
     Handle::weak_from_u128(0x3fc9d1ff70cedf02);
 pub(crate) const EYE_DOME_LIGHTING_SHADER_HANDLE: Handle<Shader> =
     Handle::weak_from_u128(0x3fc9d1ff70cedf03);
+pub(crate) const POINT_CLOUD_PICKING_FRAG_SHADER_HANDLE: Handle<Shader> =
+    Handle::weak_from_u128(0x3fc9d1ff70cedf04);
+pub(crate) const POINT_CLOUD_SPLAT_SHADER_HANDLE: Handle<Shader> =
+    Handle::weak_from_u128(0x3fc9d1ff70cedf05);
 
 #[derive(Resource)]
 pub struct PointCloudPipeline {
     pub view_layout: BindGroupLayout,
     pub entity_layout: BindGroupLayout,
     pub animated_entity_layout: BindGroupLayout,
+    /// Binds two more animation frames, for [`AnimationInterpolation::CatmullRom`](crate::AnimationInterpolation::CatmullRom).
+    pub catmull_rom_entity_layout: BindGroupLayout,
     pub model_layout: BindGroupLayout,
+    /// Bevy's shadow maps of a view, and where each light's maps are.
+    pub shadow_layout: BindGroupLayout,
+    pub shadow_sampler: Sampler,
 
     pub instanced_point_quad: Buffer,
 }
 
-#[derive(PartialEq, Eq, Hash, Clone)]
+#[derive(PartialEq, Eq, Hash, Clone, Default)]
 pub struct PointCloudPipelineKey {
     pub colored: bool,
     pub animated: bool,
+    /// Interpolates animation frames with Catmull-Rom splines instead of linearly.
+    pub catmull_rom: bool,
+    /// Reads animation frames from a buffer holding all of them, see [`AnimationUpload::AllFrames`](crate::AnimationUpload::AllFrames).
+    pub resident_frames: bool,
+    /// Animates colours and scalars, see [`AnimationFrame`](crate::AnimationFrame).
+    pub animated_attributes: bool,
+    /// The points have a normal, see [`ATTRIBUTE_NORMAL`](crate::ATTRIBUTE_NORMAL).
+    pub normals: bool,
+    /// The points have an opacity, see [`ATTRIBUTE_ALPHA`](crate::ATTRIBUTE_ALPHA).
+    pub alpha: bool,
+    /// Lights the points, see [`PointCloudShading::Lit`](crate::PointCloudShading::Lit).
+    pub lit: bool,
+    /// Samples the shadow maps of the view, with [`Self::lit`].
+    pub shadows: bool,
+    /// Only renders the depth of the points into a shadow map, see [`PointCloudShadowCaster`](crate::PointCloudShadowCaster).
+    pub shadow_pass: bool,
+    /// [`PointShape::OrientedDisk`] needs [`Self::normals`].
+    pub shape: PointShape,
+    /// Reads points from the ring buffer of a [`StreamingPointCloud`](crate::StreamingPointCloud).
+    pub streaming: bool,
+    /// Blends the points without writing depth, see [`PotreePointCloud::opacity`](crate::PotreePointCloud::opacity).
+    pub transparent: bool,
+    /// See [`PointCloudRenderMode::HighQualitySplatting`](crate::PointCloudRenderMode::HighQualitySplatting).
+    pub splat: SplatPass,
+    /// The bits of the `depth_epsilon` of [`SplatPass::Depth`].
+    pub splat_depth_epsilon: u32,
+    /// Renders point and point cloud ids instead of colors, see [`PointCloudPickingCamera`](crate::PointCloudPickingCamera).
+    pub picking: bool,
     pub msaa: u32,
 }
 
@@ -58,22 +102,45 @@ pub struct PointCloudBindGroup {
     pub bind_group: Option<BindGroup>,
     pub model_bind_group: Option<BindGroup>,
 }
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn queue_point_cloud_bind_group(
     render_device: Res<RenderDevice>,
     pipeline: Res<PointCloudPipeline>,
     view_uniform: Res<ViewUniforms>,
-    clipping_planes_uniform: Res<UniformBufferOfGpuClippingPlaneRanges>,
+    clipping_planes_buffer: Res<StorageBufferOfGpuClippingPlaneRanges>,
+    clipping_volumes_buffer: Res<StorageBufferOfGpuClippingVolumes>,
+    clipping_elements_buffer: Res<StorageBufferOfClippingElements>,
+    selection_buffer: Res<StorageBufferOfPointSelections>,
+    lights_buffer: Res<StorageBufferOfGpuPointCloudLights>,
     model_uniform: Res<ComponentUniforms<PointCloudUniform>>,
     mut bind_groups: ResMut<PointCloudBindGroup>,
 ) {
-    if let (Some(view_uniform_resource), Some(clipping_plane_resource)) = (
+    if let (
+        Some(view_uniform_resource),
+        Some(clipping_plane_resource),
+        Some(clipping_volume_resource),
+        Some(clipping_element_resource),
+        Some(selection_resource),
+        Some(lights_resource),
+    ) = (
         view_uniform.uniforms.binding(),
-        clipping_planes_uniform.0.binding(),
+        clipping_planes_buffer.0.binding(),
+        clipping_volumes_buffer.0.binding(),
+        clipping_elements_buffer.0.binding(),
+        selection_buffer.0.binding(),
+        lights_buffer.0.binding(),
     ) {
         let bind_group = render_device.create_bind_group(
             "point_cloud_bind_group",
             &pipeline.view_layout,
-            &BindGroupEntries::sequential((view_uniform_resource, clipping_plane_resource)),
+            &BindGroupEntries::sequential((
+                view_uniform_resource,
+                clipping_plane_resource,
+                clipping_volume_resource,
+                clipping_element_resource,
+                selection_resource,
+                lights_resource,
+            )),
         );
         bind_groups.bind_group = Some(bind_group);
     }
@@ -114,7 +181,47 @@ impl FromWorld for PointCloudPipeline {
                     binding: 1,
                     visibility: ShaderStages::VERTEX,
                     ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 5,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
                         has_dynamic_offset: false,
                         min_binding_size: None,
                     },
@@ -135,40 +242,30 @@ impl FromWorld for PointCloudPipeline {
                 count: None,
             }],
         });
+        let storage_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::VERTEX,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
         let animated_entity_layout =
             render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
                 label: Some("PointCloudViewLayout"),
+                entries: &[storage_entry(0), storage_entry(1), storage_entry(2)],
+            });
+        let catmull_rom_entity_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("PointCloudCatmullRomLayout"),
                 entries: &[
-                    BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::VERTEX,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: ShaderStages::VERTEX,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: ShaderStages::VERTEX,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Storage { read_only: true },
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
+                    storage_entry(0),
+                    storage_entry(1),
+                    storage_entry(2),
+                    storage_entry(3),
+                    storage_entry(4),
                 ],
             });
         let model_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
@@ -185,11 +282,58 @@ impl FromWorld for PointCloudPipeline {
             }],
         });
 
+        let shadow_layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("PointCloudShadowLayout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Depth,
+                        view_dimension: TextureViewDimension::D2Array,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Comparison),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        // Like Bevy's directional shadow sampler, nearer fragments are lit.
+        let shadow_sampler = render_device.create_sampler(&SamplerDescriptor {
+            label: Some("point_cloud_shadow_sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            compare: Some(CompareFunction::GreaterEqual),
+            ..default()
+        });
+
         Self {
             view_layout,
             model_layout,
             entity_layout,
             animated_entity_layout,
+            catmull_rom_entity_layout,
+            shadow_layout,
+            shadow_sampler,
             instanced_point_quad,
         }
     }
@@ -202,20 +346,51 @@ impl SpecializedRenderPipeline for PointCloudPipeline {
         let PointCloudPipelineKey {
             colored,
             animated,
+            catmull_rom,
+            resident_frames,
+            animated_attributes,
+            normals,
+            alpha,
+            lit,
+            shadows,
+            shadow_pass,
+            shape,
+            streaming,
+            transparent,
+            splat,
+            splat_depth_epsilon,
+            picking,
             msaa,
         } = key;
 
+        let shape_defs: Vec<ShaderDefVal> = match shape {
+            PointShape::Square => vec![],
+            PointShape::Circle => vec!["CIRCLE".into()],
+            PointShape::Paraboloid => vec!["CIRCLE".into(), "PARABOLOID".into()],
+            PointShape::OrientedDisk => vec!["CIRCLE".into(), "ORIENTED_DISK".into()],
+        };
+
+        let mut layout = vec![
+            self.view_layout.clone(),
+            if resident_frames {
+                // All frames share a binding, whatever the interpolation.
+                self.animated_entity_layout.clone()
+            } else if catmull_rom {
+                self.catmull_rom_entity_layout.clone()
+            } else if animated {
+                self.animated_entity_layout.clone()
+            } else {
+                self.entity_layout.clone()
+            },
+            self.model_layout.clone(),
+        ];
+        if shadows {
+            layout.push(self.shadow_layout.clone());
+        }
+
         RenderPipelineDescriptor {
             label: Some("point_cloud_pipeline".into()),
-            layout: vec![
-                self.view_layout.clone(),
-                if animated {
-                    self.animated_entity_layout.clone()
-                } else {
-                    self.entity_layout.clone()
-                },
-                self.model_layout.clone(),
-            ],
+            layout,
             vertex: VertexState {
                 shader: POINT_CLOUD_VERT_SHADER_HANDLE,
                 shader_defs: {
@@ -226,6 +401,39 @@ impl SpecializedRenderPipeline for PointCloudPipeline {
                     if animated {
                         defs.push("ANIMATED".into());
                     }
+                    if catmull_rom {
+                        defs.push("CATMULL_ROM".into());
+                    }
+                    if resident_frames {
+                        defs.push("RESIDENT_FRAMES".into());
+                    }
+                    if animated_attributes {
+                        defs.push("ANIMATED_ATTRIBUTES".into());
+                    }
+                    if normals {
+                        defs.push("NORMALS".into());
+                    }
+                    if alpha {
+                        defs.push("ALPHA".into());
+                    }
+                    if lit {
+                        defs.push("LIT".into());
+                    }
+                    if shadow_pass {
+                        defs.push("SHADOW_PASS".into());
+                    }
+                    if shape == PointShape::OrientedDisk {
+                        defs.push("ORIENTED_DISK".into());
+                    }
+                    if streaming {
+                        defs.push("STREAMING".into());
+                    }
+                    if transparent {
+                        defs.push("TRANSPARENT".into());
+                    }
+                    if picking {
+                        defs.push("PICKING".into());
+                    }
                     defs
                 },
                 entry_point: "main".into(),
@@ -239,31 +447,123 @@ impl SpecializedRenderPipeline for PointCloudPipeline {
                     }],
                 }],
             },
-            fragment: Some(FragmentState {
-                shader: POINT_CLOUD_FRAG_SHADER_HANDLE,
-                shader_defs: {
-                    let mut defs = Vec::new();
-                    if colored {
-                        defs.push("COLORED".into());
-                    }
-                    if animated {
-                        defs.push("ANIMATED".into());
-                    }
-                    defs
-                },
-                entry_point: "main".into(),
-                targets: vec![
-                    Some(ColorTargetState {
-                        format: TextureFormat::Rgba8UnormSrgb,
-                        blend: Some(BlendState::REPLACE),
-                        write_mask: ColorWrites::ALL,
-                    }),
-                    Some(ColorTargetState {
-                        format: TextureFormat::R32Float,
-                        blend: Some(BlendState::REPLACE),
-                        write_mask: ColorWrites::RED,
-                    }),
-                ],
+            fragment: Some(if shadow_pass {
+                FragmentState {
+                    shader: POINT_CLOUD_FRAG_SHADER_HANDLE,
+                    shader_defs: {
+                        let mut defs = vec!["SHADOW_PASS".into()];
+                        defs.extend(shape_defs);
+                        defs
+                    },
+                    entry_point: "main".into(),
+                    targets: vec![],
+                }
+            } else if picking {
+                FragmentState {
+                    shader: POINT_CLOUD_PICKING_FRAG_SHADER_HANDLE,
+                    shader_defs: {
+                        let mut defs = vec!["PICKING".into()];
+                        defs.extend(shape_defs);
+                        defs
+                    },
+                    entry_point: "main".into(),
+                    targets: vec![
+                        Some(ColorTargetState {
+                            format: POINT_CLOUD_PICKING_ID_FORMAT,
+                            blend: None,
+                            write_mask: ColorWrites::ALL,
+                        }),
+                        Some(ColorTargetState {
+                            format: POINT_CLOUD_PICKING_POSITION_FORMAT,
+                            blend: None,
+                            write_mask: ColorWrites::ALL,
+                        }),
+                    ],
+                }
+            } else {
+                FragmentState {
+                    shader: POINT_CLOUD_FRAG_SHADER_HANDLE,
+                    shader_defs: {
+                        let mut defs = Vec::new();
+                        if colored {
+                            defs.push("COLORED".into());
+                        }
+                        if animated {
+                            defs.push("ANIMATED".into());
+                        }
+                        if lit {
+                            defs.push("LIT".into());
+                        }
+                        if shadows {
+                            defs.push("SHADOWS".into());
+                        }
+                        if transparent {
+                            defs.push("TRANSPARENT".into());
+                        }
+                        defs.extend(shape_defs);
+                        match splat {
+                            SplatPass::None | SplatPass::Surface => {}
+                            SplatPass::Depth => defs.extend([
+                                "SPLAT_DEPTH".into(),
+                                ShaderDefVal::UInt(
+                                    "SPLAT_DEPTH_EPSILON_BITS".into(),
+                                    splat_depth_epsilon,
+                                ),
+                            ]),
+                            SplatPass::Accumulate => defs.push("SPLAT_ACCUMULATE".into()),
+                        }
+                        defs
+                    },
+                    entry_point: "main".into(),
+                    targets: vec![
+                        Some(match splat {
+                            SplatPass::None => ColorTargetState {
+                                format: TextureFormat::Rgba8UnormSrgb,
+                                blend: Some(if transparent {
+                                    BlendState::ALPHA_BLENDING
+                                } else {
+                                    BlendState::REPLACE
+                                }),
+                                write_mask: ColorWrites::ALL,
+                            },
+                            // Only the depth of the nearest points is needed.
+                            SplatPass::Depth | SplatPass::Surface => ColorTargetState {
+                                format: TextureFormat::Rgba8UnormSrgb,
+                                blend: None,
+                                write_mask: ColorWrites::empty(),
+                            },
+                            SplatPass::Accumulate => ColorTargetState {
+                                format: SPLAT_ACCUMULATION_FORMAT,
+                                blend: Some(BlendState {
+                                    color: BlendComponent {
+                                        src_factor: BlendFactor::One,
+                                        dst_factor: BlendFactor::One,
+                                        operation: BlendOperation::Add,
+                                    },
+                                    alpha: BlendComponent {
+                                        src_factor: BlendFactor::One,
+                                        dst_factor: BlendFactor::One,
+                                        operation: BlendOperation::Add,
+                                    },
+                                }),
+                                write_mask: ColorWrites::ALL,
+                            },
+                        }),
+                        Some(ColorTargetState {
+                            format: TextureFormat::R32Float,
+                            blend: Some(BlendState::REPLACE),
+                            // The eye dome depth is written by the surface pass, and
+                            // transparent points don't hide what is behind them.
+                            write_mask: if matches!(splat, SplatPass::Depth | SplatPass::Accumulate)
+                                || transparent
+                            {
+                                ColorWrites::empty()
+                            } else {
+                                ColorWrites::RED
+                            },
+                        }),
+                    ],
+                }
             }),
             primitive: PrimitiveState {
                 front_face: FrontFace::Ccw,
@@ -276,8 +576,14 @@ impl SpecializedRenderPipeline for PointCloudPipeline {
             },
             depth_stencil: Some(DepthStencilState {
                 format: TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: CompareFunction::Greater,
+                // Accumulates every point up to the pushed back depth of the nearest ones, and
+                // blends transparent points over each other.
+                depth_write_enabled: splat != SplatPass::Accumulate && !transparent,
+                depth_compare: if splat == SplatPass::Accumulate {
+                    CompareFunction::GreaterEqual
+                } else {
+                    CompareFunction::Greater
+                },
                 stencil: StencilState {
                     front: StencilFaceState::IGNORE,
                     back: StencilFaceState::IGNORE,
@@ -487,11 +793,13 @@ pub(crate) fn queue_view_targets(
     }
 }
 
-impl ExtractResource for PointCloudPlaybackControls {
-    type Source = Self;
+impl ExtractComponent for PlaybackControls {
+    type Query = &'static Self;
+    type Filter = With<PotreePointCloud>;
+    type Out = Self;
 
-    fn extract_resource(source: &Self::Source) -> Self {
-        source.clone()
+    fn extract_component(item: QueryItem<'_, Self::Query>) -> Option<Self> {
+        Some(*item)
     }
 }
 
@@ -499,19 +807,51 @@ pub fn prepare_animated_assets(
     queue: Res<RenderQueue>,
     render_device: Res<RenderDevice>,
     pipeline: Res<PointCloudPipeline>,
-    mut assets: ResMut<RenderAssets<PointCloudAsset>>,
-    playback: ResMut<PointCloudPlaybackControls>,
+    assets: Res<RenderAssets<PointCloudAsset>>,
+    mut instances: ResMut<PointCloudAnimationInstances>,
+    point_clouds: Query<(Entity, &Handle<PointCloudAsset>, Option<&PlaybackControls>)>,
 ) {
-    for (handle, asset) in assets.iter_mut() {
-        if asset.animation_buffer.is_some() {
-            let playback = playback
-                .controls
-                .get(&Handle::Weak(handle))
-                .copied()
-                .unwrap_or_default();
-            if playback.time != asset.animation_time {
-                asset.seek(playback.time, &queue, &render_device, &pipeline);
-            }
+    // Drop the animation buffers of despawned point clouds.
+    instances
+        .0
+        .retain(|&entity, _| point_clouds.contains(entity));
+
+    for (entity, handle, playback) in point_clouds.iter() {
+        let Some(asset) = assets.get(handle).filter(|asset| asset.animation.is_some()) else {
+            instances.0.remove(&entity);
+            continue;
+        };
+        let playback = playback.copied().unwrap_or_default();
+        let new_instance = || {
+            PointCloudAnimationInstance::new(
+                asset,
+                playback.interpolation,
+                &queue,
+                &render_device,
+                &pipeline,
+            )
+        };
+        let instance = instances
+            .0
+            .entry(entity)
+            .and_modify(|instance| {
+                if instance.point_buffer != asset.buffer.id()
+                    || instance.interpolation != playback.interpolation
+                {
+                    *instance = new_instance();
+                }
+            })
+            .or_insert_with(new_instance);
+        let time = playback.time;
+        if time != instance.animation_time {
+            instance.seek(
+                asset,
+                time,
+                playback.seek_policy,
+                &queue,
+                &render_device,
+                &pipeline,
+            );
         }
     }
 }
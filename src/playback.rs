@@ -1,17 +1,75 @@
-use bevy::{prelude::*, utils::HashMap};
+use bevy::prelude::*;
 
-use crate::PointCloudAsset;
+use crate::{PointCloudAsset, PotreePointCloud};
 
-#[derive(Resource, Clone, Default)]
-pub struct PointCloudPlaybackControls {
-    pub(crate) controls: HashMap<Handle<PointCloudAsset>, PlaybackControls>,
+/// What happens when playback reaches the end of its range.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PlaybackMode {
+    /// Playback stops at the end of the range, and a [`PlaybackFinished`] event is sent.
+    Once,
+    /// Playback wraps around to the other end of the range.
+    #[default]
+    Loop,
+    /// Playback bounces back by reversing [`PlaybackControls::speed`].
+    PingPong,
 }
 
-#[derive(Clone, Copy)]
+/// How a [`PlaybackControls::time`] outside of the animation is brought back into it, when
+/// it is set by user code.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SeekPolicy {
+    /// The time is clamped to the start or the end of the animation.
+    #[default]
+    Clamp,
+    /// The time wraps around the animation, like a looping playback.
+    Wrap,
+}
+
+impl SeekPolicy {
+    /// Brings `time` into an animation of `animation_duration` seconds. Times that aren't a
+    /// number go to the start.
+    pub fn apply(self, time: f32, animation_duration: f32) -> f32 {
+        if time.is_nan() {
+            return 0.;
+        }
+        match self {
+            SeekPolicy::Clamp => time.clamp(0., animation_duration),
+            SeekPolicy::Wrap if animation_duration > 0. && time.is_finite() => {
+                time.rem_euclid(animation_duration)
+            }
+            SeekPolicy::Wrap => 0.,
+        }
+    }
+}
+
+/// How point offsets are interpolated between animation frames.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AnimationInterpolation {
+    /// Points jump to the next frame when its timestamp is reached.
+    Step,
+    /// Points move in a straight line between frames.
+    #[default]
+    Linear,
+    /// Points follow a Catmull-Rom spline through the previous, current and next two frames,
+    /// which smooths out the motion of low frame rate captures.
+    CatmullRom,
+}
+
+/// The animation playback state of a [`PotreePointCloud`] entity.
+///
+/// Each entity has its own animation buffers on the GPU, so entities showing the
+/// same animated asset can be at different times. Entities without this component
+/// show the start of the animation.
+#[derive(Clone, Copy, Component)]
 pub struct PlaybackControls {
     pub time: f32,
     pub playing: bool,
     pub speed: f32,
+    pub mode: PlaybackMode,
+    /// The part of the animation that is played, in seconds. Defaults to the whole animation.
+    pub range: Option<(f32, f32)>,
+    pub interpolation: AnimationInterpolation,
+    pub seek_policy: SeekPolicy,
 }
 
 impl Default for PlaybackControls {
@@ -20,50 +78,304 @@ impl Default for PlaybackControls {
             time: 0.,
             playing: false,
             speed: 1.,
+            mode: PlaybackMode::Loop,
+            range: None,
+            interpolation: AnimationInterpolation::Linear,
+            seek_policy: SeekPolicy::Clamp,
         }
     }
 }
 
-impl PointCloudPlaybackControls {
-    pub fn controls(&self, handle: &Handle<PointCloudAsset>) -> PlaybackControls {
-        self.controls.get(handle).copied().unwrap_or_default()
+/// Sent when a [`PlaybackMode::Once`] playback reaches the end of its range, or its start
+/// when playing backwards.
+#[derive(Clone, Copy, Debug, Event)]
+pub struct PlaybackFinished {
+    pub entity: Entity,
+}
+
+/// Sent when a [`PlaybackMode::Loop`] playback wraps around, or a [`PlaybackMode::PingPong`]
+/// playback bounces back.
+#[derive(Clone, Copy, Debug, Event)]
+pub struct PlaybackLooped {
+    pub entity: Entity,
+}
+
+/// Sent when playback enters another animation frame.
+#[derive(Clone, Copy, Debug, Event)]
+pub struct PlaybackFrameEntered {
+    pub entity: Entity,
+    pub frame: usize,
+}
+
+/// Sent when [`PlaybackControls::time`] was outside of the animation, before it is brought
+/// back according to [`PlaybackControls::seek_policy`].
+#[derive(Clone, Copy, Debug, Event)]
+pub struct PlaybackSeekOutOfRange {
+    pub entity: Entity,
+    /// The time that was out of range.
+    pub time: f32,
+}
+
+impl PlaybackControls {
+    /// The played range, clamped to an animation of `animation_duration` seconds.
+    pub fn play_range(&self, animation_duration: f32) -> (f32, f32) {
+        let (start, end) = self.range.unwrap_or((0., animation_duration));
+        let start = start.clamp(0., animation_duration);
+        (start, end.clamp(start, animation_duration))
+    }
+
+    /// The index of the frame played at the current time, see [`PointCloudAsset::frame_index`].
+    pub fn frame_index(&self, asset: &PointCloudAsset) -> Option<usize> {
+        asset.frame_index(self.time)
+    }
+
+    /// Seeks to the timestamp of frame `index`, clamped to the last frame.
+    pub fn seek_to_frame(&mut self, asset: &PointCloudAsset, index: usize) {
+        if let Some(time) = asset.frame_time(index.min(asset.frame_count().saturating_sub(1))) {
+            self.time = time;
+        }
+    }
+
+    /// Seeks to the first frame timestamp after the current time.
+    pub fn next_frame(&mut self, asset: &PointCloudAsset) {
+        let frame_times = asset.frame_times();
+        let index = frame_times.partition_point(|&time| time <= self.time);
+        self.seek_to_frame(asset, index);
+    }
+
+    /// Seeks to the last frame timestamp before the current time.
+    pub fn previous_frame(&mut self, asset: &PointCloudAsset) {
+        let frame_times = asset.frame_times();
+        let index = frame_times.partition_point(|&time| time < self.time);
+        self.seek_to_frame(asset, index.saturating_sub(1));
     }
 
-    pub fn controls_mut(&mut self, handle: &Handle<PointCloudAsset>) -> &mut PlaybackControls {
-        self.controls.entry(handle.clone_weak()).or_default()
+    /// Seeks to the time of the marker named `name`, returns `false` if there is none.
+    pub fn seek_to_marker(&mut self, asset: &PointCloudAsset, name: &str) -> bool {
+        let Some(marker) = asset.marker(name) else {
+            return false;
+        };
+        self.time = marker.time;
+        true
     }
 
+    /// Plays `delta_seconds` of the animation, returns whether the end of the range was
+    /// passed in the direction of play, and handled according to [`Self::mode`]. Playback
+    /// starting outside of the range first moves to the range's start, or its end when
+    /// playing backwards.
+    fn advance(&mut self, delta_seconds: f32, animation_duration: f32) -> bool {
+        let (start, end) = self.play_range(animation_duration);
+        let backwards = self.speed < 0.;
+        if !(start..=end).contains(&self.time) {
+            self.time = if backwards { end } else { start };
+        }
+        self.time += self.speed * delta_seconds;
+
+        let overshoot = if backwards {
+            start - self.time
+        } else {
+            self.time - end
+        };
+        if overshoot.is_nan() || overshoot <= 0. {
+            return false;
+        }
+        let length = end - start;
+        let overshoot = if length > 0. {
+            overshoot.rem_euclid(length)
+        } else {
+            0.
+        };
+        match (self.mode, backwards) {
+            (PlaybackMode::Once, false) => self.time = end,
+            (PlaybackMode::Once, true) => self.time = start,
+            (PlaybackMode::Loop, false) => self.time = start + overshoot,
+            (PlaybackMode::Loop, true) => self.time = end - overshoot,
+            // Reflect the overshoot back into the range.
+            (PlaybackMode::PingPong, false) => self.time = end - overshoot,
+            (PlaybackMode::PingPong, true) => self.time = start + overshoot,
+        }
+        match self.mode {
+            PlaybackMode::Once => self.playing = false,
+            PlaybackMode::Loop => {}
+            PlaybackMode::PingPong => self.speed = -self.speed,
+        }
+        true
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn playback_system(
-        mut controls: ResMut<Self>,
+        mut point_clouds: Query<(Entity, &PotreePointCloud, &mut PlaybackControls)>,
         time: Res<Time>,
         assets: Res<Assets<PointCloudAsset>>,
+        mut finished_events: EventWriter<PlaybackFinished>,
+        mut looped_events: EventWriter<PlaybackLooped>,
+        mut frame_events: EventWriter<PlaybackFrameEntered>,
+        mut out_of_range_events: EventWriter<PlaybackSeekOutOfRange>,
     ) {
-        let mut changed = false;
-        let len = controls.controls.len();
-
-        controls
-            .bypass_change_detection()
-            .controls
-            .retain(|handle, controls| {
-                let Some(animation_duration) = assets
-                    .get(handle)
-                    .and_then(|asset| asset.animation_duration())
-                else {
-                    // remove if asset doesn't exist or isn't animated
-                    return false;
-                };
-
-                if controls.playing {
-                    changed |= true;
-                    controls.time += controls.speed * time.delta_seconds();
-                    controls.time = controls.time.rem_euclid(animation_duration);
+        for (entity, point_cloud, mut controls) in point_clouds.iter_mut() {
+            let Some((asset, animation_duration)) = assets
+                .get(&point_cloud.mesh)
+                .and_then(|asset| Some((asset, asset.animation_duration()?)))
+            else {
+                // skip if asset isn't loaded or isn't animated
+                continue;
+            };
+
+            // Also catches times that aren't a number.
+            if !(0.0..=animation_duration).contains(&controls.time) {
+                let time = controls.time;
+                controls.time = controls.seek_policy.apply(time, animation_duration);
+                warn!(
+                    "Playback time {time} of {entity:?} is outside of its animation, \
+                     seeking to {} instead",
+                    controls.time
+                );
+                out_of_range_events.send(PlaybackSeekOutOfRange { entity, time });
+            }
+
+            if !controls.playing {
+                continue;
+            }
+
+            let previous_frame = asset.frame_index(controls.time);
+            if controls.advance(time.delta_seconds(), animation_duration) {
+                match controls.mode {
+                    PlaybackMode::Once => finished_events.send(PlaybackFinished { entity }),
+                    PlaybackMode::Loop | PlaybackMode::PingPong => {
+                        looped_events.send(PlaybackLooped { entity })
+                    }
                 }
+            }
+
+            let frame = asset.frame_index(controls.time);
+            if let Some(frame) = frame.filter(|_| frame != previous_frame) {
+                frame_events.send(PlaybackFrameEntered { entity, frame });
+            }
+        }
+    }
+}
 
-                true
-            });
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if changed || controls.controls.len() != len {
-            controls.set_changed();
+    fn playing(time: f32, speed: f32, mode: PlaybackMode) -> PlaybackControls {
+        PlaybackControls {
+            time,
+            playing: true,
+            speed,
+            mode,
+            range: Some((2., 6.)),
+            ..default()
         }
     }
+
+    #[test]
+    fn seek_policies() {
+        assert_eq!(SeekPolicy::Clamp.apply(-1., 10.), 0.);
+        assert_eq!(SeekPolicy::Clamp.apply(12., 10.), 10.);
+        assert_eq!(SeekPolicy::Clamp.apply(f32::INFINITY, 10.), 10.);
+        assert_eq!(SeekPolicy::Clamp.apply(f32::NAN, 10.), 0.);
+        assert_eq!(SeekPolicy::Wrap.apply(12., 10.), 2.);
+        assert_eq!(SeekPolicy::Wrap.apply(-1., 10.), 9.);
+        assert_eq!(SeekPolicy::Wrap.apply(f32::INFINITY, 10.), 0.);
+        assert_eq!(SeekPolicy::Wrap.apply(f32::NAN, 10.), 0.);
+        assert_eq!(SeekPolicy::Wrap.apply(3., 0.), 0.);
+    }
+
+    #[test]
+    fn play_range_is_clamped_to_the_animation() {
+        let mut controls = PlaybackControls::default();
+        assert_eq!(controls.play_range(10.), (0., 10.));
+        controls.range = Some((2., 6.));
+        assert_eq!(controls.play_range(10.), (2., 6.));
+        controls.range = Some((-3., 12.));
+        assert_eq!(controls.play_range(10.), (0., 10.));
+        controls.range = Some((6., 2.));
+        assert_eq!(controls.play_range(10.), (6., 6.));
+        controls.range = Some((14., 16.));
+        assert_eq!(controls.play_range(10.), (10., 10.));
+    }
+
+    #[test]
+    fn playback_within_the_range() {
+        let mut controls = playing(3., 2., PlaybackMode::Once);
+        assert!(!controls.advance(1., 10.));
+        assert_eq!(controls.time, 5.);
+        // Reaching the end isn't passing it.
+        assert!(!controls.advance(0.5, 10.));
+        assert_eq!(controls.time, 6.);
+        assert!(controls.playing);
+    }
+
+    #[test]
+    fn once_finishes_at_the_end_in_the_direction_of_play() {
+        let mut controls = playing(5., 2., PlaybackMode::Once);
+        assert!(controls.advance(1., 10.));
+        assert_eq!(controls.time, 6.);
+        assert!(!controls.playing);
+
+        let mut controls = playing(3., -2., PlaybackMode::Once);
+        assert!(controls.advance(1., 10.));
+        assert_eq!(controls.time, 2.);
+        assert!(!controls.playing);
+    }
+
+    #[test]
+    fn loop_wraps_around() {
+        let mut controls = playing(5., 2., PlaybackMode::Loop);
+        assert!(controls.advance(1., 10.));
+        assert_eq!(controls.time, 3.);
+        assert!(controls.playing);
+
+        let mut controls = playing(3., -2., PlaybackMode::Loop);
+        assert!(controls.advance(1., 10.));
+        assert_eq!(controls.time, 5.);
+        assert_eq!(controls.speed, -2.);
+    }
+
+    #[test]
+    fn ping_pong_bounces_back() {
+        let mut controls = playing(5., 2., PlaybackMode::PingPong);
+        assert!(controls.advance(1., 10.));
+        assert_eq!(controls.time, 5.);
+        assert_eq!(controls.speed, -2.);
+
+        let mut controls = playing(3., -2., PlaybackMode::PingPong);
+        assert!(controls.advance(1., 10.));
+        assert_eq!(controls.time, 3.);
+        assert_eq!(controls.speed, 2.);
+    }
+
+    #[test]
+    fn playback_starting_outside_of_the_range_moves_into_it() {
+        for mode in [
+            PlaybackMode::Once,
+            PlaybackMode::Loop,
+            PlaybackMode::PingPong,
+        ] {
+            let mut controls = playing(0.5, 1., mode);
+            assert!(!controls.advance(1., 10.));
+            assert_eq!(controls.time, 3.);
+            assert_eq!(controls.speed, 1.);
+            assert!(controls.playing);
+
+            let mut controls = playing(8., 1., mode);
+            assert!(!controls.advance(1., 10.));
+            assert_eq!(controls.time, 3.);
+
+            let mut controls = playing(0.5, -1., mode);
+            assert!(!controls.advance(1., 10.));
+            assert_eq!(controls.time, 5.);
+            assert_eq!(controls.speed, -1.);
+        }
+    }
+
+    #[test]
+    fn empty_range() {
+        let mut controls = playing(4., 1., PlaybackMode::Loop);
+        controls.range = Some((4., 4.));
+        assert!(controls.advance(1., 10.));
+        assert_eq!(controls.time, 4.);
+    }
 }
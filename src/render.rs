@@ -1,14 +1,26 @@
-use crate::{pipeline::PointCloudPipeline, PointCloudAsset};
-use crate::{PointCloudPipelineKey, ATTRIBUTE_COLOR};
-use bevy::render::render_asset::RenderAssets;
+use crate::clippling_planes::ClippingRanges;
+use crate::picking::ExtractedPointCloudPickingRequest;
+use crate::selection::{PointSelection, PointSelectionRanges};
+use crate::splatting::SplatPass;
+use crate::streaming::ExtractedStreamingPointCloud;
+use crate::{pipeline::PointCloudPipeline, PointCloudAnimation, PointCloudAsset};
+use crate::{
+    AnimationInterpolation, AnimationUpload, PlaybackControls, PointCloudAssetUsage,
+    PointCloudPipelineKey, PointCloudRenderMode, PointCloudShading, PointShape, ScalarColorRamp,
+    SeekPolicy, ATTRIBUTE_ALPHA, ATTRIBUTE_COLOR, ATTRIBUTE_NORMAL,
+};
+use bevy::pbr::ViewShadowBindings;
+use bevy::render::mesh::VertexAttributeValues;
+use bevy::render::render_asset::{PrepareAssetError, RenderAssets};
 use bevy::render::render_resource::{
-    BufferDescriptor, CachedRenderPipelineId, DynamicBindGroupEntries, PipelineCache,
-    SpecializedRenderPipelines,
+    BindGroupEntries, BufferDescriptor, BufferId, CachedRenderPipelineId, DynamicBindGroupEntries,
+    PipelineCache, SpecializedRenderPipelines, VertexFormat,
 };
 use bevy::render::renderer::RenderQueue;
-use bevy::render::view::VisibleEntities;
+use bevy::render::view::{ExtractedView, VisibleEntities};
+use bevy::utils::{HashMap, HashSet};
 use bevy::{
-    ecs::system::{lifetimeless::SRes, SystemParamItem},
+    ecs::system::{lifetimeless::SRes, StaticSystemParam, SystemParamItem},
     prelude::*,
     render::{
         render_asset::RenderAsset,
@@ -17,32 +29,129 @@ use bevy::{
         Extract,
     },
 };
-use opd_parser::Frames;
+use std::ops::Range;
 #[derive(Component, Clone)]
 pub struct PotreePointCloud {
     pub mesh: Handle<PointCloudAsset>,
     pub point_size: f32,
+    /// Multiplies the [`ATTRIBUTE_ALPHA`] of the points. Point clouds with an opacity below
+    /// one are drawn after the opaque ones, back to front, without writing depth.
+    pub opacity: f32,
 }
-#[derive(Component, Clone, ShaderType)]
+
+impl PotreePointCloud {
+    /// An opaque point cloud showing `mesh`.
+    pub fn new(mesh: Handle<PointCloudAsset>, point_size: f32) -> Self {
+        Self {
+            mesh,
+            point_size,
+            opacity: 1.0,
+        }
+    }
+}
+
+impl Default for PotreePointCloud {
+    fn default() -> Self {
+        Self::new(Handle::default(), 1.0)
+    }
+}
+
+#[derive(Component, Clone, Default, ShaderType)]
 pub struct PointCloudUniform {
     pub transform: Mat4,
     pub point_size: f32,
+    /// The range of the clipping plane buffer that applies to this point cloud.
+    pub first_clipping_plane: u32,
+    pub num_clipping_planes: u32,
+    /// The range of the clipping volume buffer that applies to this point cloud.
+    pub first_clipping_volume: u32,
+    pub num_clipping_volumes: u32,
+    /// The range of the point selection buffer that applies to this point cloud.
+    pub first_selection_word: u32,
+    pub num_selection_words: u32,
+    /// Selected points are blended towards this colour, by its alpha.
+    pub selection_color: Vec4,
+    /// The animated scalars mapped to the first and last ramp colours, see [`ScalarColorRamp`].
+    pub scalar_range: Vec2,
+    pub num_ramp_colors: u32,
+    pub ramp_colors: [Vec4; ScalarColorRamp::MAX_COLORS],
+    /// The current time and age limits of a [`StreamingPointCloud`](crate::StreamingPointCloud).
+    pub stream_time: f32,
+    pub stream_max_age: f32,
+    pub stream_fade_duration: f32,
+    /// The ring buffer slot of the oldest point, and the number of slots.
+    pub stream_first: u32,
+    pub stream_capacity: u32,
+    pub opacity: f32,
+    /// Written into the ids of picked points, the index of the point cloud among the
+    /// extracted ones plus one, so zero is no point cloud.
+    pub picking_id: u32,
 }
 
+#[allow(clippy::type_complexity)]
 pub(crate) fn extract_point_cloud(
     mut commands: Commands,
     mut previous_len: Local<usize>,
-    query: Extract<Query<(Entity, &PotreePointCloud, &GlobalTransform)>>,
+    query: Extract<
+        Query<(
+            Entity,
+            &PotreePointCloud,
+            &GlobalTransform,
+            Option<&PointSelection>,
+            Option<&ScalarColorRamp>,
+        )>,
+    >,
+    clipping_ranges: Res<ClippingRanges>,
+    selection_ranges: Res<PointSelectionRanges>,
 ) {
     let mut values = Vec::with_capacity(*previous_len);
 
-    for (entity, point_cloud, transform) in query.iter() {
+    for (entity, point_cloud, transform, selection, ramp) in query.iter() {
+        let planes = clipping_ranges
+            .planes
+            .get(&entity)
+            .copied()
+            .unwrap_or_default();
+        let volumes = clipping_ranges
+            .volumes
+            .get(&entity)
+            .copied()
+            .unwrap_or_default();
+        let (first_selection_word, num_selection_words) = selection_ranges
+            .ranges
+            .get(&entity)
+            .copied()
+            .unwrap_or_default();
+        let mut ramp_colors = [Vec4::ZERO; ScalarColorRamp::MAX_COLORS];
+        let ramp = ramp.map_or(Default::default(), |ramp| {
+            for (color, ramp_color) in ramp_colors.iter_mut().zip(&ramp.colors) {
+                *color = ramp_color.as_linear_rgba_f32().into();
+            }
+            (
+                Vec2::from(ramp.range),
+                ramp.colors.len().min(ScalarColorRamp::MAX_COLORS) as u32,
+            )
+        });
         values.push((
             entity,
             (
                 PointCloudUniform {
                     transform: transform.compute_matrix(),
                     point_size: point_cloud.point_size,
+                    first_clipping_plane: planes.first,
+                    num_clipping_planes: planes.count,
+                    first_clipping_volume: volumes.first,
+                    num_clipping_volumes: volumes.count,
+                    first_selection_word,
+                    num_selection_words,
+                    selection_color: selection
+                        .map_or(Vec4::ZERO, |s| s.color.as_linear_rgba_f32().into()),
+                    scalar_range: ramp.0,
+                    num_ramp_colors: ramp.1,
+                    ramp_colors,
+                    opacity: point_cloud.opacity,
+                    picking_id: values.len() as u32 + 1,
+                    ..default()
                 },
                 point_cloud.mesh.clone(),
             ),
@@ -60,40 +169,157 @@ pub struct PointCloudDrawList {
 pub struct PointCloudDrawData {
     pub entity: Entity,
     pub pipeline_id: CachedRenderPipelineId,
+    /// Only set for views that pick points this frame.
+    pub picking_pipeline_id: Option<CachedRenderPipelineId>,
+    /// The depth, accumulation and surface pipelines, only set for views using
+    /// [`PointCloudRenderMode::HighQualitySplatting`].
+    pub splat_pipeline_ids: Option<[CachedRenderPipelineId; 3]>,
+    /// Drawn after the opaque point clouds, see [`PotreePointCloud::opacity`].
+    pub transparent: bool,
+}
+
+/// Specializes the pipelines of the [`SplatPass::Depth`], [`SplatPass::Accumulate`] and
+/// [`SplatPass::Surface`] passes, the depth pass with the `depth_epsilon` of the view.
+fn specialize_splat_pipelines(
+    pipelines: &mut SpecializedRenderPipelines<PointCloudPipeline>,
+    cache: &PipelineCache,
+    pipeline: &PointCloudPipeline,
+    key: &PointCloudPipelineKey,
+    depth_epsilon: f32,
+) -> [CachedRenderPipelineId; 3] {
+    [SplatPass::Depth, SplatPass::Accumulate, SplatPass::Surface].map(|splat| {
+        let key = PointCloudPipelineKey {
+            splat,
+            splat_depth_epsilon: if splat == SplatPass::Depth {
+                depth_epsilon.to_bits()
+            } else {
+                0
+            },
+            ..key.clone()
+        };
+        pipelines.specialize(cache, pipeline, key)
+    })
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub(crate) fn queue_point_cloud(
     pipeline: Res<PointCloudPipeline>,
     mut pipelines: ResMut<SpecializedRenderPipelines<PointCloudPipeline>>,
     cache: Res<PipelineCache>,
-    views: Query<(Entity, &VisibleEntities)>,
-    items: Query<&Handle<PointCloudAsset>>,
+    views: Query<(
+        Entity,
+        &ExtractedView,
+        &VisibleEntities,
+        Has<ExtractedPointCloudPickingRequest>,
+        Option<&PointCloudRenderMode>,
+        Has<ViewShadowBindings>,
+    )>,
+    items: Query<(
+        &Handle<PointCloudAsset>,
+        &PointCloudUniform,
+        Option<&PlaybackControls>,
+        Option<&PointCloudShading>,
+        Option<&PointShape>,
+    )>,
+    streams: Query<Option<&PointShape>, With<ExtractedStreamingPointCloud>>,
     point_clouds: Res<RenderAssets<PointCloudAsset>>,
     msaa: Option<Res<Msaa>>,
     mut commands: Commands,
 ) {
     let msaa = msaa.map(|a| a.samples()).unwrap_or(1);
-    for (view_entity, entities) in &views {
+    for (view_entity, view, entities, picking, render_mode, shadows) in &views {
+        let splat_depth_epsilon = match render_mode {
+            Some(&PointCloudRenderMode::HighQualitySplatting { depth_epsilon }) => {
+                Some(depth_epsilon)
+            }
+            _ => None,
+        };
         let mut list = vec![];
+        let mut transparent_list = vec![];
         for &entity in &entities.entities {
-            if let Some(asset) = items
+            if let Some((asset, uniform, playback, shading, shape)) = items
                 .get(entity)
                 .ok()
-                .and_then(|handle| point_clouds.get(handle))
+                .and_then(|(handle, uniform, playback, shading, shape)| {
+                    Some((
+                        point_clouds.get(handle)?,
+                        uniform,
+                        playback.copied().unwrap_or_default(),
+                        shading.copied().unwrap_or_default(),
+                        shape.copied().unwrap_or_default(),
+                    ))
+                })
             {
+                let transparent = asset.alpha || uniform.opacity < 1.0;
+                let key = asset.pipeline_key(playback, shading, shape);
                 let key = PointCloudPipelineKey {
-                    colored: asset.colored,
-                    animated: asset.animation_buffer.is_some(),
+                    shadows: key.lit && shadows,
+                    transparent,
                     msaa,
+                    ..key
                 };
 
-                let pipeline_id = pipelines.specialize(&cache, &pipeline, key);
-                list.push(PointCloudDrawData {
+                let pipeline_id = pipelines.specialize(&cache, &pipeline, key.clone());
+                let picking_pipeline_id = picking.then(|| {
+                    let key = PointCloudPipelineKey {
+                        lit: false,
+                        shadows: false,
+                        transparent: false,
+                        picking: true,
+                        msaa: 1,
+                        ..key
+                    };
+                    pipelines.specialize(&cache, &pipeline, key)
+                });
+                // Transparent point clouds are blended over the splats.
+                let splat_pipeline_ids =
+                    splat_depth_epsilon.filter(|_| !transparent).map(|epsilon| {
+                        specialize_splat_pipelines(&mut pipelines, &cache, &pipeline, &key, epsilon)
+                    });
+                let draw_data = PointCloudDrawData {
                     entity,
                     pipeline_id,
+                    picking_pipeline_id,
+                    splat_pipeline_ids,
+                    transparent,
+                };
+                if transparent {
+                    let distance = view
+                        .transform
+                        .translation()
+                        .distance_squared(uniform.transform.w_axis.truncate());
+                    transparent_list.push((distance, draw_data));
+                } else {
+                    list.push(draw_data);
+                }
+            } else if let Ok(shape) = streams.get(entity) {
+                let key = PointCloudPipelineKey {
+                    colored: true,
+                    // Streamed points have no normals to orient disks with.
+                    shape: match shape.copied().unwrap_or_default() {
+                        PointShape::OrientedDisk => PointShape::Circle,
+                        shape => shape,
+                    },
+                    streaming: true,
+                    msaa,
+                    ..default()
+                };
+                let splat_pipeline_ids = splat_depth_epsilon.map(|epsilon| {
+                    specialize_splat_pipelines(&mut pipelines, &cache, &pipeline, &key, epsilon)
+                });
+                list.push(PointCloudDrawData {
+                    entity,
+                    pipeline_id: pipelines.specialize(&cache, &pipeline, key),
+                    picking_pipeline_id: None,
+                    splat_pipeline_ids,
+                    transparent: false,
                 });
             }
         }
+        // Back to front, by the distance to the origins of the point clouds, since the points
+        // of transparent point clouds don't write depth.
+        transparent_list.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        list.extend(transparent_list.into_iter().map(|(_, draw_data)| draw_data));
         if !list.is_empty() {
             commands
                 .entity(view_entity)
@@ -105,161 +331,374 @@ pub(crate) fn queue_point_cloud(
 pub struct PreparedPointCloudAsset {
     pub buffer: Buffer,
     pub num_points: u32,
+    /// The number of points [`Self::buffer`] has room for, more than [`Self::num_points`]
+    /// after points were removed.
+    pub capacity: u32,
+    /// Only used by point clouds without animation, animated ones use the bind group
+    /// of their [`PointCloudAnimationInstance`].
     pub bind_group: Option<BindGroup>,
 
-    pub animation_buffer: Option<(Buffer, Buffer)>,
-    pub frames: Option<Frames>,
-    pub current_animation_frame: usize,
-    pub animation_time: f32,
-    pub animation_frame_start_time: f32,
-    pub animation_scale: Vec3,
+    /// The animation, with every frame turned into offsets.
+    pub animation: Option<PointCloudAnimation>,
+    /// Every animation frame, after a frame of zero offsets, for assets using
+    /// [`AnimationUpload::AllFrames`].
+    pub resident_frames: Option<Buffer>,
 
     pub colored: bool,
+    /// Whether the points have an [`ATTRIBUTE_NORMAL`].
+    pub normals: bool,
+    /// Whether the points have an [`ATTRIBUTE_ALPHA`].
+    pub alpha: bool,
 }
 
 impl PreparedPointCloudAsset {
-    pub fn seek(
+    /// The key of the pipelines drawing the asset, before the options of the view and entity.
+    pub(crate) fn pipeline_key(
+        &self,
+        playback: PlaybackControls,
+        shading: PointCloudShading,
+        shape: PointShape,
+    ) -> PointCloudPipelineKey {
+        let animated = self.animation.is_some();
+        PointCloudPipelineKey {
+            colored: self.colored,
+            animated,
+            catmull_rom: animated && playback.interpolation == AnimationInterpolation::CatmullRom,
+            resident_frames: self.resident_frames.is_some(),
+            animated_attributes: self
+                .animation
+                .as_ref()
+                .is_some_and(PointCloudAnimation::has_attributes),
+            normals: self.normals,
+            alpha: self.alpha,
+            lit: self.normals && shading == PointCloudShading::Lit,
+            shape: match shape {
+                PointShape::OrientedDisk if !self.normals => PointShape::Circle,
+                shape => shape,
+            },
+            ..default()
+        }
+    }
+
+    pub fn update_bind_group(
         &mut self,
-        seek_to: f32, // time from the start of the animation to seek to
-        queue: &RenderQueue,
         render_device: &RenderDevice,
         pipeline: &PointCloudPipeline,
     ) {
-        let (prev_animation_buffer, next_animation_buffer) = self.animation_buffer.as_mut().expect(
-            "Cannot call PreparedPointCloudAsset::seek on an instance without an animation",
+        let bind_group = render_device.create_bind_group(
+            "point cloud buffer bind group",
+            &pipeline.entity_layout,
+            &BindGroupEntries::single(self.buffer.as_entire_binding()),
         );
-        let frames = match self.frames.as_ref().unwrap() {
-            Frames::I8(frames) => frames,
-            _ => todo!(), // make some kinda trait abstraction
-        };
+        self.bind_group = Some(bind_group);
+    }
 
-        self.animation_time = seek_to;
+    /// The number of floats of each animation frame on the GPU.
+    fn frame_len(&self) -> usize {
+        self.animation.as_ref().map_or(0, |animation| {
+            self.num_points as usize * animation.floats_per_point()
+        })
+    }
 
-        // If we're already in the correct frame, adjust interpolation and exit
-        let current_frame_end_time = frames[self.current_animation_frame].time / 1000.;
-        if (self.animation_frame_start_time..current_frame_end_time).contains(&self.animation_time)
-        {
-            let duration = current_frame_end_time - self.animation_frame_start_time;
-            let delta = self.animation_time - self.animation_frame_start_time;
-            let interpolation = (delta / duration).min(1.0);
+    /// Uploads every animation frame into one buffer, after a frame of zero offsets.
+    fn create_resident_frames(&self, render_device: &RenderDevice) -> Option<Buffer> {
+        let animation = self.animation.as_ref()?;
+        let frame_len = self.frame_len();
+        if frame_len == 0 {
+            return None;
+        }
+        let len = (animation.len() + 1) * frame_len;
+        let size = (len * std::mem::size_of::<f32>()) as u64;
+        if size > render_device.limits().max_storage_buffer_binding_size as u64 {
+            warn!(
+                "The {} animation frames of a point cloud don't fit in a storage buffer, \
+                 uploading them on seek instead",
+                animation.len()
+            );
+            return None;
+        }
+        let mut offsets = vec![0.0; len];
+        for (frame, view) in offsets.chunks_exact_mut(frame_len).skip(1).enumerate() {
+            animation.write_frame_offsets(frame, view);
+        }
+        Some(
+            render_device.create_buffer_with_data(&BufferInitDescriptor {
+                usage: BufferUsages::STORAGE,
+                label: Some("Point cloud animation frames"),
+                contents: bytemuck::cast_slice(&offsets),
+            }),
+        )
+    }
+}
 
-            queue.write_buffer(next_animation_buffer, 0, bytemuck::bytes_of(&interpolation));
+/// The GPU animation state of a [`PotreePointCloud`] entity with an animated asset.
+///
+/// Keeps a small pool of frame buffers resident, holding the frames around the current
+/// time, so seeking to a neighbouring frame only uploads the frame that is missing. When the
+/// asset has [`PreparedPointCloudAsset::resident_frames`], seeking only writes the indices of
+/// the frames to interpolate instead.
+pub struct PointCloudAnimationInstance {
+    /// The asset buffer the bind group was created with, the instance is recreated when
+    /// the asset is prepared again.
+    pub point_buffer: BufferId,
+    pub interpolation: AnimationInterpolation,
+    /// The frame buffers, each starting with an interpolation factor followed by the offsets
+    /// of a frame, or by zeros before the first frame (`None`).
+    frame_buffers: Vec<(Buffer, Option<usize>)>,
+    /// The frame buffer bound to each animation binding, see [`Self::needed_frames`].
+    bound_buffers: Vec<usize>,
+    /// The interpolation factor, point count and bound frame slots of
+    /// [`PreparedPointCloudAsset::resident_frames`], when the asset has them.
+    frame_indices: Option<Buffer>,
+    pub animation_time: f32,
+    pub bind_group: BindGroup,
+}
 
-            return;
+/// The animation instances of point cloud entities, which persist across frames.
+#[derive(Resource, Default)]
+pub struct PointCloudAnimationInstances(pub HashMap<Entity, PointCloudAnimationInstance>);
+
+impl PointCloudAnimationInstances {
+    /// Returns the bind group the point cloud entity should be drawn with.
+    pub fn bind_group<'a>(
+        &'a self,
+        entity: Entity,
+        asset: &'a PreparedPointCloudAsset,
+    ) -> Option<&'a BindGroup> {
+        if asset.animation.is_some() {
+            self.0.get(&entity).map(|instance| &instance.bind_group)
+        } else {
+            asset.bind_group.as_ref()
         }
+    }
+}
 
-        let to_enter = if self.current_animation_frame == frames.len() - 1 && {
-            // if we're on the last frame, check if we're seeking to the first frame first
-            let first_frame_end_time = frames[0].time / 1000.;
-            self.animation_time < first_frame_end_time
-        } {
-            0
-        } else if self.current_animation_frame != frames.len() - 1 && {
-            // if we're not on the last frame, check if we're seeking to the next frame first
-            let next_frame_end_time = frames[self.current_animation_frame + 1].time / 1000.;
-            (current_frame_end_time..next_frame_end_time).contains(&self.animation_time)
-        } {
-            self.current_animation_frame + 1
+impl PointCloudAnimationInstance {
+    pub fn new(
+        asset: &PreparedPointCloudAsset,
+        interpolation: AnimationInterpolation,
+        queue: &RenderQueue,
+        render_device: &RenderDevice,
+        pipeline: &PointCloudPipeline,
+    ) -> Self {
+        let size = (asset.frame_len() + 1) as u64 * std::mem::size_of::<f32>() as u64;
+        let create_buffer = |label, size| {
+            render_device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        let (frame_buffers, frame_indices) = if asset.resident_frames.is_some() {
+            (
+                Vec::new(),
+                Some(create_buffer(
+                    "AnimationFrameIndices",
+                    std::mem::size_of::<[u32; 6]>() as u64,
+                )),
+            )
         } else {
-            // if we're seeking to neither, find what frame we're seeking to using binary search
-            match frames.binary_search_by(|f| (f.time / 1000.).partial_cmp(&seek_to).unwrap()) {
-                // Landed on the exact end of a frame
-                // If its the last frame, stay on the last frame
-                // If its not, we will enter the next frame
-                Ok(index) if index == frames.len() - 1 => index,
-                Ok(index) => index + 1,
-                // The index is to the frame where `delta_seconds` is smaller than the indexed frame, but larger than the previous,
-                // or its out of bounds
-                Err(index) if index == frames.len() => panic!("Out of bounds seek"),
-                Err(index) => index,
-            }
+            // Buffers are zero initialized, so they start out holding no offsets.
+            let frame_buffers = (0..Self::num_bindings(interpolation))
+                .map(|_| (create_buffer("AnimationBuffer", size), None))
+                .collect();
+            (frame_buffers, None)
         };
+        let bound_buffers: Vec<usize> = (0..frame_buffers.len()).collect();
+        let bind_group = Self::create_bind_group(
+            asset,
+            &frame_buffers,
+            &bound_buffers,
+            frame_indices.as_ref(),
+            render_device,
+            pipeline,
+        );
+        let mut instance = Self {
+            point_buffer: asset.buffer.id(),
+            interpolation,
+            frame_buffers,
+            bound_buffers,
+            frame_indices,
+            animation_time: f32::NAN,
+            bind_group,
+        };
+        instance.seek(
+            asset,
+            0.0,
+            SeekPolicy::Clamp,
+            queue,
+            render_device,
+            pipeline,
+        );
+        instance
+    }
+
+    fn num_bindings(interpolation: AnimationInterpolation) -> usize {
+        match interpolation {
+            AnimationInterpolation::CatmullRom => 4,
+            _ => 2,
+        }
+    }
+
+    /// The frames bound to the animation bindings when playing the frame `to_enter`: the
+    /// previous and next frames, then the frames before and after them for Catmull-Rom
+    /// interpolation. `None` stands for the zero offsets before the first frame.
+    fn needed_frames(&self, to_enter: usize, last_frame: usize) -> Vec<Option<usize>> {
+        let mut frames = vec![to_enter.checked_sub(1), Some(to_enter)];
+        if self.interpolation == AnimationInterpolation::CatmullRom {
+            frames.push(to_enter.checked_sub(2));
+            frames.push(Some((to_enter + 1).min(last_frame)));
+        }
+        frames
+    }
 
-        let mut view = vec![0.0; self.num_points as usize * 3];
+    pub fn seek(
+        &mut self,
+        asset: &PreparedPointCloudAsset,
+        seek_to: f32, // time from the start of the animation to seek to
+        seek_policy: SeekPolicy,
+        queue: &RenderQueue,
+        render_device: &RenderDevice,
+        pipeline: &PointCloudPipeline,
+    ) {
+        let animation = asset.animation.as_ref().expect(
+            "Cannot call PointCloudAnimationInstance::seek with an asset without an animation",
+        );
+        let frames = animation.frames();
+
+        // Playback keeps the time within the animation, see `SeekPolicy`, but it may have
+        // been set after the playback system ran.
+        let animation_duration = frames[frames.len() - 1].time;
+        self.animation_time = seek_to;
+        let seek_to = seek_policy.apply(seek_to, animation_duration);
+
+        // The frame we're entering is the first one that ends at or after the seek time.
+        let to_enter = animation.frame_index(seek_to).unwrap();
 
-        if to_enter == self.current_animation_frame + 1 {
-            // We're going to the next frame, so the start time is simply the current end time
-            self.animation_frame_start_time = current_frame_end_time;
+        // Calculate the interpolation for the frame we just entered
+        let frame_start_time = match to_enter {
+            0 => 0.,
+            _ => frames[to_enter - 1].time,
+        };
+        let duration = frames[to_enter].time - frame_start_time;
+        let delta = seek_to - frame_start_time;
+        let interpolation = if duration > 0. {
+            (delta / duration).clamp(0.0, 1.0)
         } else {
-            // We're not going to the next frame, so the current next_animation_buffer cannot
-            // be used as the upcoming prev_animation_buffer directly.
-            // Adjust the next_animation_buffer to point to the frame before the one we're entering
-            // or zero it out if we're entering the first frame
-
-            if to_enter == 0 {
-                // We're entering the first frame, set the start time to zero
-                self.animation_frame_start_time = 0.;
-            } else {
-                // We're not entering the first frame, setup `view` with the values in frame `to_enter - 1`
-                // Also set the frame start time
-                for (i, arr) in frames[to_enter - 1].into_iter().enumerate() {
-                    let arr = Vec3::from(arr) * self.animation_scale;
-                    for j in 0..3 {
-                        view[i * 3 + j] = arr[j];
-                    }
-                }
-                self.animation_frame_start_time = frames[to_enter - 1].time / 1000.;
-            }
+            1.0
+        };
+        let interpolation = match self.interpolation {
+            AnimationInterpolation::Step if interpolation < 1.0 => 0.0,
+            _ => interpolation,
+        };
+        let needed = self.needed_frames(to_enter, frames.len() - 1);
 
-            // Write the buffer
-            queue.write_buffer(next_animation_buffer, 4, bytemuck::cast_slice(&view));
+        if let Some(frame_indices) = &self.frame_indices {
+            // Slot 0 of the resident frames holds zero offsets, frame `i` is in slot `i + 1`.
+            let slot = |binding: usize| {
+                needed
+                    .get(binding)
+                    .copied()
+                    .flatten()
+                    .map_or(0, |frame| frame as u32 + 1)
+            };
+            let indices = [
+                interpolation.to_bits(),
+                asset.num_points,
+                slot(0),
+                slot(1),
+                slot(2),
+                slot(3),
+            ];
+            queue.write_buffer(frame_indices, 0, bytemuck::cast_slice(&indices));
+            return;
         }
 
-        // Swap the buffers
-        std::mem::swap(next_animation_buffer, prev_animation_buffer);
-
-        // If we're moving to the previous frame, the values in the next_animation_buffer are
-        // already set up for it thanks to the swap, so we can skip this step
-        if to_enter + 1 != self.current_animation_frame {
-            // Setup view with values for the frame we're entering
-            for (i, arr) in frames[to_enter].into_iter().enumerate() {
-                let arr = Vec3::from(arr) * self.animation_scale;
-                for j in 0..3 {
-                    view[i * 3 + j] = arr[j];
-                }
+        // Bind a frame buffer to each binding, reusing the buffers that already hold the
+        // needed frames and uploading the missing frames into the others.
+        let mut bound: Vec<Option<usize>> = needed
+            .iter()
+            .map(|&frame| self.frame_buffers.iter().position(|(_, f)| *f == frame))
+            .collect();
+        // The same buffer may be needed by several bindings, like the zero offsets at the start.
+        let mut view = None;
+        for binding in 0..needed.len() {
+            if bound[binding].is_some() {
+                continue;
             }
-
-            // Write the values into next_animation_buffer
-            queue.write_buffer(next_animation_buffer, 4, bytemuck::cast_slice(&view));
+            let free = (0..self.frame_buffers.len())
+                .find(|buffer| !bound.contains(&Some(*buffer)))
+                .unwrap();
+            let view = view.get_or_insert_with(|| vec![0.0; asset.frame_len()]);
+            view.fill(0.0);
+            if let Some(frame) = needed[binding] {
+                animation.write_frame_offsets(frame, view);
+            }
+            let (buffer, frame) = &mut self.frame_buffers[free];
+            queue.write_buffer(buffer, 4, bytemuck::cast_slice(view));
+            *frame = needed[binding];
+            bound[binding] = Some(free);
         }
+        let bound: Vec<usize> = bound.into_iter().map(Option::unwrap).collect();
 
-        self.current_animation_frame = to_enter;
-
-        // Calculate and write interpolation for the frame we just entered
-        let current_frame_end_time = frames[self.current_animation_frame].time / 1000.;
-        let duration = current_frame_end_time - self.animation_frame_start_time;
-        let delta = self.animation_time - self.animation_frame_start_time;
-        let interpolation = (delta / duration).min(1.0);
-
+        let (next_animation_buffer, _) = &self.frame_buffers[bound[1]];
         queue.write_buffer(next_animation_buffer, 0, bytemuck::bytes_of(&interpolation));
 
-        // Update the bind group, since we swapped the buffers.
-        self.update_bind_group(render_device, pipeline);
+        // Update the bind group if the buffers moved between bindings.
+        if bound != self.bound_buffers {
+            self.bind_group = Self::create_bind_group(
+                asset,
+                &self.frame_buffers,
+                &bound,
+                None,
+                render_device,
+                pipeline,
+            );
+            self.bound_buffers = bound;
+        }
     }
 
-    pub fn update_bind_group(
-        &mut self,
+    fn create_bind_group(
+        asset: &PreparedPointCloudAsset,
+        frame_buffers: &[(Buffer, Option<usize>)],
+        bound_buffers: &[usize],
+        frame_indices: Option<&Buffer>,
         render_device: &RenderDevice,
         pipeline: &PointCloudPipeline,
-    ) {
-        let mut bind_group_entries =
-            DynamicBindGroupEntries::sequential((self.buffer.as_entire_binding(),));
-        if let Some((animation_buffer, next)) = self.animation_buffer.as_ref() {
-            bind_group_entries = bind_group_entries.extend_sequential((
-                animation_buffer.as_entire_binding(),
-                next.as_entire_binding(),
-            ));
+    ) -> BindGroup {
+        if let (Some(resident_frames), Some(frame_indices)) =
+            (&asset.resident_frames, frame_indices)
+        {
+            return render_device.create_bind_group(
+                "point cloud animation bind group",
+                &pipeline.animated_entity_layout,
+                &BindGroupEntries::sequential((
+                    asset.buffer.as_entire_binding(),
+                    resident_frames.as_entire_binding(),
+                    frame_indices.as_entire_binding(),
+                )),
+            );
+        }
+        let buffer = |binding: usize| frame_buffers[bound_buffers[binding]].0.as_entire_binding();
+        let bind_group_entries = DynamicBindGroupEntries::sequential((
+            asset.buffer.as_entire_binding(),
+            buffer(0),
+            buffer(1),
+        ));
+        if bound_buffers.len() == 4 {
+            render_device.create_bind_group(
+                "point cloud animation bind group",
+                &pipeline.catmull_rom_entity_layout,
+                &bind_group_entries.extend_sequential((buffer(2), buffer(3))),
+            )
+        } else {
+            render_device.create_bind_group(
+                "point cloud animation bind group",
+                &pipeline.animated_entity_layout,
+                &bind_group_entries,
+            )
         }
-        let bind_group = render_device.create_bind_group(
-            "point cloud buffer bind group",
-            if self.animation_buffer.is_some() {
-                &pipeline.animated_entity_layout
-            } else {
-                &pipeline.entity_layout
-            },
-            &bind_group_entries,
-        );
-        self.bind_group = Some(bind_group);
     }
 }
 
@@ -277,54 +716,357 @@ impl RenderAsset for PointCloudAsset {
     fn prepare_asset(
         extracted_asset: Self::ExtractedAsset,
         (render_device, pipeline): &mut SystemParamItem<Self::Param>,
-    ) -> Result<
-        Self::PreparedAsset,
-        bevy::render::render_asset::PrepareAssetError<Self::ExtractedAsset>,
-    > {
+    ) -> Result<Self::PreparedAsset, PrepareAssetError<Self::ExtractedAsset>> {
         let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            usage: BufferUsages::STORAGE,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
             label: Some("Point cloud vertex buffer"),
             contents: extracted_asset.mesh.get_vertex_buffer_data().as_slice(),
         });
 
-        let animation_buffer = if extracted_asset.animation.is_some() {
-            let size = extracted_asset
-                .mesh
-                .attribute(Mesh::ATTRIBUTE_POSITION)
-                .unwrap()
-                .len() as u64
-                * std::mem::size_of::<f32>() as u64
-                * 3
-                + std::mem::size_of::<f32>() as u64;
-            let animation_buffer = render_device.create_buffer(&BufferDescriptor {
-                label: Some("AnimationBuffer"),
-                size,
-                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
-                mapped_at_creation: false,
-            });
-            let animation_buffer_next = render_device.create_buffer(&BufferDescriptor {
-                label: Some("AnimationBufferNext"),
-                size,
-                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
-                mapped_at_creation: false,
-            });
-            Some((animation_buffer, animation_buffer_next))
-        } else {
-            None
+        let colors = match extracted_asset.mesh.attribute(ATTRIBUTE_COLOR) {
+            Some(VertexAttributeValues::Float32x3(colors)) => Some(colors.as_slice()),
+            _ => None,
+        };
+        let animation = match extracted_asset.mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float32x3(positions)) => extracted_asset
+                .animation
+                .filter(|animation| !animation.is_empty())
+                .map(|animation| animation.into_offsets(positions, colors)),
+            _ => None,
         };
+
+        let num_points = extracted_asset.mesh.count_vertices() as u32;
         let mut asset = PreparedPointCloudAsset {
             buffer,
-            num_points: extracted_asset.mesh.count_vertices() as u32,
+            num_points,
+            capacity: num_points,
             bind_group: None,
-            animation_buffer,
-            frames: extracted_asset.animation,
-            current_animation_frame: 0,
-            animation_time: 0.0,
-            animation_frame_start_time: 0.0,
-            animation_scale: extracted_asset.animation_scale,
+            animation,
+            resident_frames: None,
             colored: extracted_asset.mesh.contains_attribute(ATTRIBUTE_COLOR),
+            normals: extracted_asset.mesh.contains_attribute(ATTRIBUTE_NORMAL),
+            alpha: extracted_asset.mesh.contains_attribute(ATTRIBUTE_ALPHA),
         };
+        if extracted_asset.animation_upload == AnimationUpload::AllFrames {
+            asset.resident_frames = asset.create_resident_frames(render_device);
+        }
         asset.update_bind_group(render_device, pipeline);
         Ok(asset)
     }
 }
+
+/// A [`PointCloudAsset`] extracted to the render world.
+pub enum ExtractedPointCloudAsset {
+    /// The whole asset, prepared again from scratch.
+    Full(PointCloudAsset),
+    /// Only the vertex data of the edited points, written into the existing buffer.
+    Points {
+        num_points: u32,
+        /// Byte offsets into the vertex buffer, and the interleaved vertex data written there.
+        writes: Vec<(u64, Vec<u8>)>,
+    },
+}
+
+/// The point cloud assets changed since the last frame, replacing the
+/// [`RenderAssetPlugin`](bevy::render::render_asset::RenderAssetPlugin) of
+/// [`PointCloudAsset`] so edited points can be uploaded without the rest of the asset.
+#[derive(Resource, Default)]
+pub struct ExtractedPointCloudAssets {
+    extracted: Vec<(AssetId<PointCloudAsset>, ExtractedPointCloudAsset)>,
+    removed: Vec<AssetId<PointCloudAsset>>,
+}
+
+pub(crate) fn extract_point_cloud_assets(
+    mut events: Extract<EventReader<AssetEvent<PointCloudAsset>>>,
+    assets: Extract<Res<Assets<PointCloudAsset>>>,
+    render_assets: Res<RenderAssets<PointCloudAsset>>,
+    mut extracted_assets: ResMut<ExtractedPointCloudAssets>,
+) {
+    let mut changed = HashSet::default();
+    let mut removed = Vec::new();
+    for event in events.read() {
+        match event {
+            AssetEvent::Added { id } | AssetEvent::Modified { id } => {
+                changed.insert(*id);
+            }
+            AssetEvent::Removed { id } => {
+                changed.remove(id);
+                removed.push(*id);
+            }
+            AssetEvent::LoadedWithDependencies { .. } => {}
+        }
+    }
+
+    let mut extracted = Vec::new();
+    for id in changed {
+        let Some(asset) = assets.get(id) else {
+            continue;
+        };
+        if asset.usage() == PointCloudAssetUsage::RenderWorld {
+            // Later changes aren't uploaded, the main world has no points left.
+            let Some(mesh) = asset.render_world_mesh.take() else {
+                continue;
+            };
+            asset.changed_points.take();
+            // Cheap, the main world mesh is empty and the animation is shared.
+            let mut extracted_asset = asset.clone();
+            extracted_asset.mesh = mesh;
+            extracted.push((id, ExtractedPointCloudAsset::Full(extracted_asset)));
+            continue;
+        }
+        let prepared = render_assets.get(id).map(PreparedLayout::from);
+        extracted.push((id, extract_changed_asset(asset, prepared)));
+    }
+    *extracted_assets = ExtractedPointCloudAssets { extracted, removed };
+}
+
+/// What [`extract_changed_points`] needs to know of a [`PreparedPointCloudAsset`].
+#[derive(Clone, Copy)]
+struct PreparedLayout {
+    capacity: u32,
+    animated: bool,
+    colored: bool,
+    normals: bool,
+    alpha: bool,
+}
+
+impl From<&PreparedPointCloudAsset> for PreparedLayout {
+    fn from(prepared: &PreparedPointCloudAsset) -> Self {
+        Self {
+            capacity: prepared.capacity,
+            animated: prepared.animation.is_some(),
+            colored: prepared.colored,
+            normals: prepared.normals,
+            alpha: prepared.alpha,
+        }
+    }
+}
+
+/// Takes the changed points of `asset`, and extracts only them if the asset was `prepared`.
+fn extract_changed_asset(
+    asset: &PointCloudAsset,
+    prepared: Option<PreparedLayout>,
+) -> ExtractedPointCloudAsset {
+    let changed_points = asset.changed_points.take();
+    prepared
+        .zip(changed_points)
+        .and_then(|(prepared, changed_points)| {
+            extract_changed_points(asset, prepared, &changed_points)
+        })
+        .unwrap_or_else(|| ExtractedPointCloudAsset::Full(asset.extract_asset()))
+}
+
+/// Copies the vertex data of `changed_points`, unless the asset has to be prepared again
+/// because its layout, size or animation changed. Only updates the number of points if no
+/// point was edited.
+fn extract_changed_points(
+    asset: &PointCloudAsset,
+    prepared: PreparedLayout,
+    changed_points: &[Range<u32>],
+) -> Option<ExtractedPointCloudAsset> {
+    let num_points = asset.mesh().count_vertices() as u32;
+    if asset.animation.is_some()
+        || prepared.animated
+        || prepared.colored != asset.mesh().contains_attribute(ATTRIBUTE_COLOR)
+        || prepared.normals != asset.mesh().contains_attribute(ATTRIBUTE_NORMAL)
+        || prepared.alpha != asset.mesh().contains_attribute(ATTRIBUTE_ALPHA)
+        || num_points > prepared.capacity
+    {
+        return None;
+    }
+    let writes = changed_points
+        .iter()
+        .filter_map(|range| {
+            let range = range.start as usize..(range.end.min(num_points)) as usize;
+            if range.is_empty() {
+                return None;
+            }
+            let offset = range.start * vertex_size(asset.mesh());
+            Some((offset as u64, vertex_buffer_data(asset.mesh(), range)))
+        })
+        .collect();
+    Some(ExtractedPointCloudAsset::Points { num_points, writes })
+}
+
+fn vertex_size(mesh: &Mesh) -> usize {
+    mesh.attributes()
+        .map(|(_, values)| VertexFormat::from(values).size() as usize)
+        .sum()
+}
+
+/// The vertex data of the points in `range`, interleaved like `Mesh::get_vertex_buffer_data`
+/// in attribute order.
+fn vertex_buffer_data(mesh: &Mesh, range: Range<usize>) -> Vec<u8> {
+    let vertex_size = vertex_size(mesh);
+    let mut data = vec![0; range.len() * vertex_size];
+    let mut attribute_offset = 0;
+    for (_, values) in mesh.attributes() {
+        let bytes = values.get_bytes();
+        let attribute_size = VertexFormat::from(values).size() as usize;
+        for (i, vertex) in range.clone().enumerate() {
+            let offset = i * vertex_size + attribute_offset;
+            data[offset..offset + attribute_size]
+                .copy_from_slice(&bytes[vertex * attribute_size..(vertex + 1) * attribute_size]);
+        }
+        attribute_offset += attribute_size;
+    }
+    data
+}
+
+/// The point cloud assets that couldn't be prepared yet, like Bevy's `PrepareNextFrameAssets`.
+#[derive(Resource, Default)]
+pub struct PrepareNextFramePointCloudAssets {
+    assets: Vec<(AssetId<PointCloudAsset>, PointCloudAsset)>,
+}
+
+pub(crate) fn prepare_point_cloud_assets(
+    mut extracted_assets: ResMut<ExtractedPointCloudAssets>,
+    mut render_assets: ResMut<RenderAssets<PointCloudAsset>>,
+    mut prepare_next_frame: ResMut<PrepareNextFramePointCloudAssets>,
+    param: StaticSystemParam<<PointCloudAsset as RenderAsset>::Param>,
+    render_queue: Res<RenderQueue>,
+) {
+    let mut param = param.into_inner();
+    let queued_assets = std::mem::take(&mut prepare_next_frame.assets);
+    let mut prepare = |render_assets: &mut RenderAssets<PointCloudAsset>, id, asset| {
+        match PointCloudAsset::prepare_asset(asset, &mut param) {
+            Ok(prepared) => {
+                render_assets.insert(id, prepared);
+            }
+            Err(PrepareAssetError::RetryNextUpdate(asset)) => {
+                prepare_next_frame.assets.push((id, asset));
+            }
+        }
+    };
+    // Before the edited points, which may have been extracted after them.
+    for (id, asset) in queued_assets {
+        prepare(&mut render_assets, id, asset);
+    }
+    for id in std::mem::take(&mut extracted_assets.removed) {
+        render_assets.remove(id);
+    }
+    for (id, extracted_asset) in std::mem::take(&mut extracted_assets.extracted) {
+        match extracted_asset {
+            ExtractedPointCloudAsset::Full(asset) => prepare(&mut render_assets, id, asset),
+            ExtractedPointCloudAsset::Points { num_points, writes } => {
+                let Some(prepared) = render_assets.get_mut(id) else {
+                    continue;
+                };
+                for (offset, data) in writes {
+                    render_queue.write_buffer(&prepared.buffer, offset, &data);
+                }
+                prepared.num_points = num_points;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::render_resource::PrimitiveTopology;
+
+    fn prepared_layout(asset: &PointCloudAsset) -> PreparedLayout {
+        PreparedLayout {
+            capacity: asset.mesh().count_vertices() as u32,
+            animated: false,
+            colored: true,
+            normals: false,
+            alpha: false,
+        }
+    }
+
+    fn point_cloud(num_points: usize) -> PointCloudAsset {
+        let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            (0..num_points)
+                .map(|i| [i as f32, 0.0, 0.0])
+                .collect::<Vec<_>>(),
+        );
+        mesh.insert_attribute(ATTRIBUTE_COLOR, vec![[1.0f32; 3]; num_points]);
+        PointCloudAsset::new(mesh)
+    }
+
+    #[test]
+    fn removing_the_last_points_only_updates_the_count() {
+        let mut asset = point_cloud(4);
+        let prepared = prepared_layout(&asset);
+        asset.remove_points([3, 2]);
+        match extract_changed_asset(&asset, Some(prepared)) {
+            ExtractedPointCloudAsset::Points { num_points, writes } => {
+                assert_eq!(num_points, 2);
+                assert!(writes.is_empty());
+            }
+            ExtractedPointCloudAsset::Full(_) => panic!("uploaded the whole asset"),
+        }
+    }
+
+    #[test]
+    fn edited_points_are_written_into_the_buffer() {
+        let mut asset = point_cloud(4);
+        let prepared = prepared_layout(&asset);
+        asset.set_positions(2, &[Vec3::splat(5.0)]);
+        match extract_changed_asset(&asset, Some(prepared)) {
+            ExtractedPointCloudAsset::Points { num_points, writes } => {
+                assert_eq!(num_points, 4);
+                let vertex_size = vertex_size(asset.mesh()) as u64;
+                assert_eq!(
+                    writes.iter().map(|(offset, _)| *offset).collect::<Vec<_>>(),
+                    [2 * vertex_size]
+                );
+            }
+            ExtractedPointCloudAsset::Full(_) => panic!("uploaded the whole asset"),
+        }
+    }
+
+    #[test]
+    fn unrecorded_or_unprepared_assets_are_uploaded_whole() {
+        let mut asset = point_cloud(4);
+        let prepared = prepared_layout(&asset);
+        asset.mesh_mut();
+        assert!(matches!(
+            extract_changed_asset(&asset, Some(prepared)),
+            ExtractedPointCloudAsset::Full(_)
+        ));
+        asset.set_positions(0, &[Vec3::ONE]);
+        assert!(matches!(
+            extract_changed_asset(&asset, None),
+            ExtractedPointCloudAsset::Full(_)
+        ));
+        // More points than the buffer has room for.
+        let mut grown = point_cloud(5);
+        grown.set_positions(4, &[Vec3::ONE]);
+        assert!(matches!(
+            extract_changed_asset(&grown, Some(prepared)),
+            ExtractedPointCloudAsset::Full(_)
+        ));
+    }
+
+    #[test]
+    fn vertex_buffer_data_matches_mesh() {
+        let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            (0..6).map(|i| [i as f32, 1.0, 2.0]).collect::<Vec<_>>(),
+        );
+        mesh.insert_attribute(
+            ATTRIBUTE_COLOR,
+            (0..6).map(|i| [0.1, i as f32, 0.3]).collect::<Vec<_>>(),
+        );
+        mesh.insert_attribute(
+            ATTRIBUTE_ALPHA,
+            (0..6).map(|i| i as f32 / 6.0).collect::<Vec<_>>(),
+        );
+        let vertex_size = vertex_size(&mesh);
+        assert_eq!(vertex_size, 28);
+
+        let all = mesh.get_vertex_buffer_data();
+        for range in [0..6, 2..5, 5..6, 3..3] {
+            assert_eq!(
+                vertex_buffer_data(&mesh, range.clone()),
+                all[range.start * vertex_size..range.end * vertex_size]
+            );
+        }
+    }
+}
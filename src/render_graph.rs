@@ -1,20 +1,28 @@
 use crate::pipeline::{EyeDomeViewTarget, PointCloudBindGroup, PointCloudPipeline};
-use crate::{PointCloudAsset, PointCloudDrawList, PointCloudUniform};
+use crate::render::PointCloudDrawData;
+use crate::shadows::PointCloudShadowBindGroup;
+use crate::splatting::SplatViewTarget;
+use crate::streaming::{ExtractedStreamingPointCloud, StreamingPointCloudBuffers};
+use crate::{PointCloudAnimationInstances, PointCloudAsset, PointCloudDrawList, PointCloudUniform};
 use bevy::ecs::query::QueryItem;
 use bevy::prelude::*;
 use bevy::render::camera::ExtractedCamera;
 use bevy::render::extract_component::DynamicUniformIndex;
 use bevy::render::render_asset::RenderAssets;
 use bevy::render::render_graph::ViewNode;
+use bevy::render::render_phase::TrackedRenderPass;
 use bevy::render::render_resource::{
-    LoadOp, Operations, PipelineCache, RenderPassColorAttachment, RenderPassDepthStencilAttachment,
-    RenderPassDescriptor, ShaderStages,
+    CachedRenderPipelineId, LoadOp, Operations, PipelineCache, RenderPassColorAttachment,
+    RenderPassDepthStencilAttachment, RenderPassDescriptor, ShaderStages, TextureView,
 };
+use bevy::render::renderer::RenderContext;
 use bevy::render::view::{ExtractedView, ViewDepthTexture, ViewTarget, ViewUniformOffset};
 
 pub struct PointCloudNode {
+    #[allow(clippy::type_complexity)]
     entity_query: QueryState<(
-        &'static Handle<PointCloudAsset>,
+        Option<&'static Handle<PointCloudAsset>>,
+        Option<&'static ExtractedStreamingPointCloud>,
         &'static DynamicUniformIndex<PointCloudUniform>,
     )>,
 }
@@ -23,6 +31,118 @@ impl PointCloudNode {
     pub const NAME: &'static str = "point_cloud_node";
 }
 
+impl PointCloudNode {
+    /// Draws the point clouds of `draw_list` with the pipelines picked by `pipeline_id`.
+    fn draw_point_clouds<'w>(
+        &self,
+        tracked_pass: &mut TrackedRenderPass<'w>,
+        world: &'w World,
+        view_uniform_offset: &ViewUniformOffset,
+        shadow_bind_group: Option<&'w PointCloudShadowBindGroup>,
+        draw_list: &PointCloudDrawList,
+        pipeline_id: impl Fn(&PointCloudDrawData) -> Option<CachedRenderPipelineId>,
+    ) {
+        let point_cloud_pipeline = world.resource::<PointCloudPipeline>();
+        let bind_groups = world.resource::<PointCloudBindGroup>();
+        let (Some(bind_group), Some(model_bind_group)) = (
+            bind_groups.bind_group.as_ref(),
+            bind_groups.model_bind_group.as_ref(),
+        ) else {
+            return;
+        };
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let render_assets = world.resource::<RenderAssets<PointCloudAsset>>();
+        let animation_instances = world.resource::<PointCloudAnimationInstances>();
+        let streaming_buffers = world.resource::<StreamingPointCloudBuffers>();
+
+        tracked_pass.set_bind_group(0, bind_group, &[view_uniform_offset.offset]);
+        tracked_pass.set_vertex_buffer(0, point_cloud_pipeline.instanced_point_quad.slice(0..32));
+        // Only used by the pipelines of lit point clouds.
+        if let Some(shadow_bind_group) = shadow_bind_group {
+            tracked_pass.set_bind_group(3, &shadow_bind_group.0, &[]);
+        }
+        for draw_data in &draw_list.list {
+            let Some(pipeline) =
+                pipeline_id(draw_data).and_then(|id| pipeline_cache.get_render_pipeline(id))
+            else {
+                continue;
+            };
+            let Ok((point_cloud_asset, streaming, dynamic_index)) =
+                self.entity_query.get_manual(world, draw_data.entity)
+            else {
+                continue;
+            };
+            let bind_group_and_num_points = if let Some(streaming) = streaming {
+                streaming_buffers
+                    .0
+                    .get(&draw_data.entity)
+                    .and_then(|buffer| buffer.bind_group.as_ref())
+                    .map(|bind_group| (bind_group, streaming.num_points))
+            } else {
+                point_cloud_asset
+                    .and_then(|handle| render_assets.get(handle))
+                    .and_then(|asset| {
+                        animation_instances
+                            .bind_group(draw_data.entity, asset)
+                            .map(|bind_group| (bind_group, asset.num_points))
+                    })
+            };
+            let Some((point_cloud_bind_group, num_points)) = bind_group_and_num_points else {
+                continue;
+            };
+
+            tracked_pass.set_render_pipeline(pipeline);
+            tracked_pass.set_bind_group(1, point_cloud_bind_group, &[]);
+            tracked_pass.set_bind_group(2, model_bind_group, &[dynamic_index.index()]);
+            tracked_pass.draw(0..4, 0..num_points);
+        }
+    }
+}
+
+/// Begins a pass drawing point clouds into `color_attachment` and the depth textures.
+fn begin_point_cloud_pass<'a>(
+    render_context: &'a mut RenderContext,
+    label: &'static str,
+    color_attachment: RenderPassColorAttachment<'a>,
+    eye_dome_view_target: &'a EyeDomeViewTarget,
+    clear_eye_dome_depth: bool,
+    depth_view: &'a TextureView,
+    camera: &ExtractedCamera,
+) -> TrackedRenderPass<'a> {
+    let mut tracked_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[
+            Some(color_attachment),
+            Some(RenderPassColorAttachment {
+                view: &eye_dome_view_target.depth_texture_view,
+                resolve_target: None,
+                ops: Operations {
+                    load: if clear_eye_dome_depth {
+                        LoadOp::Clear(Color::BLACK.into())
+                    } else {
+                        LoadOp::Load
+                    },
+                    store: true,
+                },
+            }),
+        ],
+        depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+            view: depth_view,
+            // NOTE: The opaque main pass loads the depth buffer and possibly overwrites it
+            depth_ops: Some(Operations {
+                // NOTE: 0.0 is the far plane due to bevy's use of reverse-z projections.
+                load: LoadOp::Load,
+                store: true,
+            }),
+            stencil_ops: None,
+        }),
+    });
+    if let Some(viewport) = camera.viewport.as_ref() {
+        tracked_pass.set_camera_viewport(viewport);
+    }
+    tracked_pass
+}
+
 impl FromWorld for PointCloudNode {
     fn from_world(world: &mut World) -> Self {
         Self {
@@ -40,6 +160,8 @@ impl ViewNode for PointCloudNode {
         &'static ViewUniformOffset,
         &'static EyeDomeViewTarget,
         &'static PointCloudDrawList,
+        Option<&'static SplatViewTarget>,
+        Option<&'static PointCloudShadowBindGroup>,
     );
 
     fn update(&mut self, world: &mut World) {
@@ -50,120 +172,212 @@ impl ViewNode for PointCloudNode {
         &self,
         _graph: &mut bevy::render::render_graph::RenderGraphContext,
         render_context: &mut bevy::render::renderer::RenderContext,
-        (view, camera, target, depth, view_uniform_offset, eye_dome_view_target, draw_list): QueryItem<Self::ViewQuery>,
+        (
+            view,
+            camera,
+            target,
+            depth,
+            view_uniform_offset,
+            eye_dome_view_target,
+            draw_list,
+            splat_view_target,
+            shadow_bind_group,
+        ): QueryItem<Self::ViewQuery>,
         world: &World,
     ) -> Result<(), bevy::render::render_graph::NodeRunError> {
         let point_cloud_pipeline = world.resource::<PointCloudPipeline>();
         let pipeline_cache = world.resource::<PipelineCache>();
-        let render_assets = world.resource::<RenderAssets<PointCloudAsset>>();
 
-        let mut tracked_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-            label: Some("point_cloud"),
-            // NOTE: The opaque pass loads the color
-            // buffer as well as writing to it.
-            color_attachments: &[
-                Some(target.get_color_attachment(Operations {
-                    load: LoadOp::Load,
-                    store: true,
-                })),
-                Some(RenderPassColorAttachment {
-                    view: &eye_dome_view_target.depth_texture_view,
-                    resolve_target: None,
+        let bind_groups = world.resource::<PointCloudBindGroup>();
+        if bind_groups.bind_group.is_none() || bind_groups.model_bind_group.is_none() {
+            return Ok(());
+        }
+        // NOTE: The opaque pass loads the color
+        // buffer as well as writing to it.
+        let color_attachment = target.get_color_attachment(Operations {
+            load: LoadOp::Load,
+            store: true,
+        });
+
+        if let Some(splat_view_target) = splat_view_target {
+            // Finds the nearest surface, pushed back by the depth epsilon, in a copy of the view
+            // depth so the pushed back surface doesn't leak into the passes that follow.
+            render_context.command_encoder().copy_texture_to_texture(
+                depth.texture.as_image_copy(),
+                splat_view_target.depth_texture.as_image_copy(),
+                depth.texture.size(),
+            );
+            let mut tracked_pass = begin_point_cloud_pass(
+                render_context,
+                "point_cloud_splat_depth",
+                color_attachment,
+                eye_dome_view_target,
+                true,
+                &splat_view_target.depth_view,
+                camera,
+            );
+            self.draw_point_clouds(
+                &mut tracked_pass,
+                world,
+                view_uniform_offset,
+                shadow_bind_group,
+                draw_list,
+                |draw_data| draw_data.splat_pipeline_ids.map(|ids| ids[0]),
+            );
+            drop(tracked_pass);
+
+            // Sums the weighted colours of the points in front of that surface.
+            let mut tracked_pass = begin_point_cloud_pass(
+                render_context,
+                "point_cloud_splat_accumulate",
+                RenderPassColorAttachment {
+                    view: &splat_view_target.accumulation_view,
+                    resolve_target: splat_view_target.resolve_view.as_deref(),
                     ops: Operations {
-                        load: LoadOp::Clear(Color::BLACK.into()),
+                        load: LoadOp::Clear(Color::NONE.into()),
                         store: true,
                     },
-                }),
-            ],
-            depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
-                view: &depth.view,
-                // NOTE: The opaque main pass loads the depth buffer and possibly overwrites it
-                depth_ops: Some(Operations {
-                    // NOTE: 0.0 is the far plane due to bevy's use of reverse-z projections.
+                },
+                eye_dome_view_target,
+                false,
+                &splat_view_target.depth_view,
+                camera,
+            );
+            self.draw_point_clouds(
+                &mut tracked_pass,
+                world,
+                view_uniform_offset,
+                shadow_bind_group,
+                draw_list,
+                |draw_data| draw_data.splat_pipeline_ids.map(|ids| ids[1]),
+            );
+            drop(tracked_pass);
+
+            if let Some(normalize_pipeline) =
+                pipeline_cache.get_render_pipeline(splat_view_target.pipeline_id)
+            {
+                let mut tracked_pass =
+                    render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                        label: Some("point_cloud_splat_normalize"),
+                        color_attachments: &[Some(target.get_color_attachment(Operations {
+                            load: LoadOp::Load,
+                            store: true,
+                        }))],
+                        depth_stencil_attachment: None,
+                    });
+                if let Some(viewport) = camera.viewport.as_ref() {
+                    tracked_pass.set_camera_viewport(viewport);
+                }
+                tracked_pass.set_render_pipeline(normalize_pipeline);
+                tracked_pass.set_bind_group(0, &splat_view_target.bind_group, &[]);
+                tracked_pass
+                    .set_vertex_buffer(0, point_cloud_pipeline.instanced_point_quad.slice(0..32));
+                tracked_pass.draw(0..4, 0..1);
+            }
+
+            // Writes the depth of the nearest points, without the depth epsilon.
+            let mut tracked_pass = begin_point_cloud_pass(
+                render_context,
+                "point_cloud_splat_surface",
+                target.get_color_attachment(Operations {
                     load: LoadOp::Load,
                     store: true,
                 }),
-                stencil_ops: None,
-            }),
-        });
-        if let Some(viewport) = camera.viewport.as_ref() {
-            tracked_pass.set_camera_viewport(viewport);
+                eye_dome_view_target,
+                false,
+                &depth.view,
+                camera,
+            );
+            self.draw_point_clouds(
+                &mut tracked_pass,
+                world,
+                view_uniform_offset,
+                shadow_bind_group,
+                draw_list,
+                |draw_data| draw_data.splat_pipeline_ids.map(|ids| ids[2]),
+            );
+        } else {
+            let mut tracked_pass = begin_point_cloud_pass(
+                render_context,
+                "point_cloud",
+                color_attachment,
+                eye_dome_view_target,
+                true,
+                &depth.view,
+                camera,
+            );
+            self.draw_point_clouds(
+                &mut tracked_pass,
+                world,
+                view_uniform_offset,
+                shadow_bind_group,
+                draw_list,
+                |draw_data| (!draw_data.transparent).then_some(draw_data.pipeline_id),
+            );
         }
 
-        let bind_groups = world.resource::<PointCloudBindGroup>();
-        if bind_groups.bind_group.is_none() || bind_groups.model_bind_group.is_none() {
-            return Ok(());
-        }
-        tracked_pass.set_bind_group(
-            0,
-            bind_groups.bind_group.as_ref().unwrap(),
-            &[view_uniform_offset.offset],
-        );
-        tracked_pass.set_vertex_buffer(0, point_cloud_pipeline.instanced_point_quad.slice(0..32));
-        for draw_data in &draw_list.list {
-            let Some(pipeline) = pipeline_cache.get_render_pipeline(draw_data.pipeline_id) else {
-                continue;
-            };
-            let Ok((point_cloud_asset, dynamic_index)) =
-                self.entity_query.get_manual(world, draw_data.entity)
-            else {
-                continue;
-            };
-            let Some(point_cloud_asset) = render_assets.get(point_cloud_asset) else {
-                continue;
+        if let Some(eye_dome_pipeline) =
+            pipeline_cache.get_render_pipeline(eye_dome_view_target.pipeline_id)
+        {
+            let mut tracked_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("eye_dome_lighting"),
+                // NOTE: The opaque pass loads the color
+                // buffer as well as writing to it.
+                color_attachments: &[Some(target.get_color_attachment(Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                }))],
+                depth_stencil_attachment: None,
+            });
+            if let Some(viewport) = camera.viewport.as_ref() {
+                tracked_pass.set_camera_viewport(viewport);
+            }
+            tracked_pass.set_render_pipeline(eye_dome_pipeline);
+
+            let edl_strength: f32 = if view.projection.z_axis.w == -1.0 {
+                // perspective projection
+                // See https://github.com/bitshifter/glam-rs/blob/a35030d130c0464cbb07d6404df6843240182803/src/f32/scalar/mat4.rs#L843
+                1.0
+            } else {
+                // orthographic projection
+                // See https://github.com/bitshifter/glam-rs/blob/a35030d130c0464cbb07d6404df6843240182803/src/f32/scalar/mat4.rs#L924
+                1.0 / view.projection.z_axis.z // near - far
             };
 
-            tracked_pass.set_render_pipeline(pipeline);
-            tracked_pass.set_bind_group(1, point_cloud_asset.bind_group.as_ref().unwrap(), &[]);
-            tracked_pass.set_bind_group(
-                2,
-                bind_groups.model_bind_group.as_ref().unwrap(),
-                &[dynamic_index.index()],
+            tracked_pass.set_push_constants(
+                ShaderStages::FRAGMENT,
+                0,
+                bytemuck::bytes_of(&edl_strength),
             );
-            tracked_pass.draw(0..4, 0..point_cloud_asset.num_points);
-        }
-        drop(tracked_pass);
-
-        let eye_dome_pipeline =
-            pipeline_cache.get_render_pipeline(eye_dome_view_target.pipeline_id);
-        if eye_dome_pipeline.is_none() {
-            return Ok(());
+            tracked_pass.set_bind_group(0, &eye_dome_view_target.bind_group, &[]);
+            tracked_pass
+                .set_vertex_buffer(0, point_cloud_pipeline.instanced_point_quad.slice(0..32));
+            tracked_pass.draw(0..4, 0..1);
         }
-        let eye_dome_pipeline = eye_dome_pipeline.unwrap();
 
-        let mut tracked_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
-            label: Some("eye_dome_lighting"),
-            // NOTE: The opaque pass loads the color
-            // buffer as well as writing to it.
-            color_attachments: &[Some(target.get_color_attachment(Operations {
-                load: LoadOp::Load,
-                store: true,
-            }))],
-            depth_stencil_attachment: None,
-        });
-        if let Some(viewport) = camera.viewport.as_ref() {
-            tracked_pass.set_camera_viewport(viewport);
+        // Blended over the shaded opaque points.
+        if draw_list.list.iter().any(|draw_data| draw_data.transparent) {
+            let mut tracked_pass = begin_point_cloud_pass(
+                render_context,
+                "point_cloud_transparent",
+                target.get_color_attachment(Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                }),
+                eye_dome_view_target,
+                false,
+                &depth.view,
+                camera,
+            );
+            self.draw_point_clouds(
+                &mut tracked_pass,
+                world,
+                view_uniform_offset,
+                shadow_bind_group,
+                draw_list,
+                |draw_data| draw_data.transparent.then_some(draw_data.pipeline_id),
+            );
         }
-        tracked_pass.set_render_pipeline(eye_dome_pipeline);
-
-        let edl_strength: f32 = if view.projection.z_axis.w == -1.0 {
-            // perspective projection
-            // See https://github.com/bitshifter/glam-rs/blob/a35030d130c0464cbb07d6404df6843240182803/src/f32/scalar/mat4.rs#L843
-            1.0
-        } else {
-            // orthographic projection
-            // See https://github.com/bitshifter/glam-rs/blob/a35030d130c0464cbb07d6404df6843240182803/src/f32/scalar/mat4.rs#L924
-            1.0 / view.projection.z_axis.z // near - far
-        };
-
-        tracked_pass.set_push_constants(
-            ShaderStages::FRAGMENT,
-            0,
-            bytemuck::bytes_of(&edl_strength),
-        );
-        tracked_pass.set_bind_group(0, &eye_dome_view_target.bind_group, &[]);
-        tracked_pass.set_vertex_buffer(0, point_cloud_pipeline.instanced_point_quad.slice(0..32));
-        tracked_pass.draw(0..4, 0..1);
         Ok(())
     }
 }
@@ -0,0 +1,464 @@
+use bevy::{
+    prelude::*,
+    render::{mesh::VertexAttributeValues, render_resource::StorageBuffer, Extract},
+    utils::HashMap,
+};
+
+use crate::{PointCloudAsset, PotreePointCloud};
+
+/// A set of selected points of a [`PotreePointCloud`], highlighted on the GPU.
+///
+/// The selection is stored as a bitmask of point indices, and is only uploaded
+/// again when it changes. It follows the points as long as their indices stay the same, see
+/// [`Self::remove_points`] for [`PointCloudAsset::remove_points`].
+#[derive(Clone, Component, Debug)]
+pub struct PointSelection {
+    words: Vec<u32>,
+    /// Selected points are blended towards this colour, by its alpha.
+    pub color: Color,
+}
+
+impl Default for PointSelection {
+    fn default() -> Self {
+        Self {
+            words: Vec::new(),
+            color: Color::rgba(1.0, 0.8, 0.0, 0.8),
+        }
+    }
+}
+
+/// How a set of points is combined with a [`PointSelection`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SelectionOp {
+    /// The points are added to the selection.
+    #[default]
+    Add,
+    /// The points are removed from the selection.
+    Remove,
+    /// Selected points are deselected and the others are selected.
+    Toggle,
+    /// The selection is cleared first.
+    Replace,
+}
+
+impl PointSelection {
+    pub fn new(color: Color) -> Self {
+        Self {
+            words: Vec::new(),
+            color,
+        }
+    }
+
+    pub fn contains(&self, index: u32) -> bool {
+        self.words
+            .get(index as usize / 32)
+            .is_some_and(|word| word & (1 << (index % 32)) != 0)
+    }
+
+    pub fn insert(&mut self, index: u32) {
+        let word = index as usize / 32;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (index % 32);
+    }
+
+    pub fn remove(&mut self, index: u32) {
+        if let Some(word) = self.words.get_mut(index as usize / 32) {
+            *word &= !(1 << (index % 32));
+        }
+    }
+
+    pub fn toggle(&mut self, index: u32) {
+        if self.contains(index) {
+            self.remove(index);
+        } else {
+            self.insert(index);
+        }
+    }
+
+    /// Combines the selection with `indices`.
+    pub fn apply(&mut self, op: SelectionOp, indices: impl IntoIterator<Item = u32>) {
+        if op == SelectionOp::Replace {
+            self.clear();
+        }
+        for index in indices {
+            match op {
+                SelectionOp::Add | SelectionOp::Replace => self.insert(index),
+                SelectionOp::Remove => self.remove(index),
+                SelectionOp::Toggle => self.toggle(index),
+            }
+        }
+    }
+
+    /// Selects every unselected point of a point cloud with `num_points` points, and
+    /// deselects the others.
+    pub fn invert(&mut self, num_points: u32) {
+        let num_words = (num_points as usize).div_ceil(32);
+        self.words.resize(num_words, 0);
+        for word in &mut self.words {
+            *word = !*word;
+        }
+        // Deselect the bits past the last point.
+        let last_bits = num_points % 32;
+        if last_bits > 0 {
+            self.words[num_words - 1] &= (1 << last_bits) - 1;
+        }
+    }
+
+    /// Removes the points at `indices` like [`PointCloudAsset::remove_points`] does on a point
+    /// cloud with `num_points` points, so the points moved into their place stay selected.
+    pub fn remove_points(&mut self, mut num_points: u32, indices: impl IntoIterator<Item = u32>) {
+        let mut indices: Vec<u32> = indices
+            .into_iter()
+            .filter(|&index| index < num_points)
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+        for &index in indices.iter().rev() {
+            num_points -= 1;
+            if index < num_points && self.contains(num_points) {
+                self.insert(index);
+            } else {
+                self.remove(index);
+            }
+            self.remove(num_points);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.words.clear();
+    }
+
+    /// The number of selected points.
+    pub fn len(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.iter().all(|&word| word == 0)
+    }
+
+    /// The selected point indices, in increasing order.
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, &word)| {
+            (0..32)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| i as u32 * 32 + bit)
+        })
+    }
+}
+
+/// Returns the indices of the points of `asset` whose projection lies inside of a polygon, in
+/// logical viewport coordinates of `camera`. This implements box and lasso selections, brush
+/// selections can use [`PointCloudSpatialQuery::within_radius`](crate::PointCloudSpatialQuery::within_radius).
+///
+/// Points behind the camera are never selected. The polygon is filled with the even-odd rule.
+pub fn points_in_viewport_polygon(
+    asset: &PointCloudAsset,
+    transform: &GlobalTransform,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    polygon: &[Vec2],
+) -> Vec<u32> {
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        asset.mesh().attribute(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return Vec::new();
+    };
+    if polygon.len() < 3 {
+        return Vec::new();
+    }
+    let (min, max) = polygon.iter().fold(
+        (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+        |(min, max), &vertex| (min.min(vertex), max.max(vertex)),
+    );
+    positions
+        .iter()
+        .enumerate()
+        .filter_map(|(index, &position)| {
+            let world = transform.transform_point(Vec3::from(position));
+            let screen = camera.world_to_viewport(camera_transform, world)?;
+            let inside = screen.cmpge(min).all()
+                && screen.cmple(max).all()
+                && is_inside_polygon(polygon, screen);
+            inside.then_some(index as u32)
+        })
+        .collect()
+}
+
+/// Returns the indices of the points of `asset` whose projection lies inside of `rect`, in
+/// logical viewport coordinates of `camera`.
+pub fn points_in_viewport_rect(
+    asset: &PointCloudAsset,
+    transform: &GlobalTransform,
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    rect: Rect,
+) -> Vec<u32> {
+    let polygon = [
+        rect.min,
+        Vec2::new(rect.max.x, rect.min.y),
+        rect.max,
+        Vec2::new(rect.min.x, rect.max.y),
+    ];
+    points_in_viewport_polygon(asset, transform, camera, camera_transform, &polygon)
+}
+
+/// Even-odd point in polygon test.
+pub(crate) fn is_inside_polygon(polygon: &[Vec2], point: Vec2) -> bool {
+    let mut inside = false;
+    let mut previous = polygon[polygon.len() - 1];
+    for &vertex in polygon {
+        if (vertex.y > point.y) != (previous.y > point.y)
+            && point.x
+                < (previous.x - vertex.x) * (point.y - vertex.y) / (previous.y - vertex.y)
+                    + vertex.x
+        {
+            inside = !inside;
+        }
+        previous = vertex;
+    }
+    inside
+}
+
+/// The bitmasks of every [`PointSelection`], concatenated.
+#[derive(Resource, Default)]
+pub struct StorageBufferOfPointSelections(pub(crate) StorageBuffer<Vec<u32>>);
+
+/// The range of [`StorageBufferOfPointSelections`] used by each point cloud.
+#[derive(Resource, Default)]
+pub(crate) struct PointSelectionRanges {
+    pub ranges: HashMap<Entity, (u32, u32)>,
+    /// Whether the buffer needs to be uploaded again.
+    pub dirty: bool,
+}
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn extract_point_selections(
+    selections: Extract<Query<(Entity, Ref<PointSelection>), With<PotreePointCloud>>>,
+    mut selection_buffer: ResMut<StorageBufferOfPointSelections>,
+    mut ranges: ResMut<PointSelectionRanges>,
+) {
+    // Rebuild the buffer when a selection was added, changed or removed.
+    let unchanged = selections.iter().len() == ranges.ranges.len()
+        && selections.iter().all(|(entity, selection)| {
+            !selection.is_changed() && ranges.ranges.contains_key(&entity)
+        });
+    if unchanged {
+        return;
+    }
+
+    let words = selection_buffer.0.get_mut();
+    words.clear();
+    ranges.ranges.clear();
+    for (entity, selection) in selections.iter() {
+        ranges
+            .ranges
+            .insert(entity, (words.len() as u32, selection.words.len() as u32));
+        words.extend_from_slice(&selection.words);
+    }
+    if words.is_empty() {
+        // Storage buffer bindings can't be empty.
+        words.push(0);
+    }
+    ranges.dirty = true;
+}
+
+pub(crate) fn prepare_point_selections(
+    render_device: Res<bevy::render::renderer::RenderDevice>,
+    render_queue: Res<bevy::render::renderer::RenderQueue>,
+    mut selection_buffer: ResMut<StorageBufferOfPointSelections>,
+    mut ranges: ResMut<PointSelectionRanges>,
+) {
+    if ranges.dirty || selection_buffer.0.buffer().is_none() {
+        if selection_buffer.0.get().is_empty() {
+            selection_buffer.0.get_mut().push(0);
+        }
+        selection_buffer
+            .0
+            .write_buffer(&render_device, &render_queue);
+        ranges.dirty = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        ecs::system::RunSystemOnce,
+        render::{
+            camera::{camera_system, ManualTextureViews, RenderTarget},
+            render_resource::PrimitiveTopology,
+        },
+        window::{WindowCreated, WindowRef, WindowResized, WindowResolution},
+    };
+
+    use super::*;
+
+    fn selection(indices: impl IntoIterator<Item = u32>) -> PointSelection {
+        let mut selection = PointSelection::default();
+        selection.apply(SelectionOp::Add, indices);
+        selection
+    }
+
+    fn indices(selection: &PointSelection) -> Vec<u32> {
+        selection.iter().collect()
+    }
+
+    #[test]
+    fn selection_ops() {
+        let mut points = selection([1, 3, 40]);
+        assert_eq!(indices(&points), [1, 3, 40]);
+        assert_eq!(points.len(), 3);
+
+        points.apply(SelectionOp::Remove, [3, 100]);
+        assert_eq!(indices(&points), [1, 40]);
+
+        points.apply(SelectionOp::Toggle, [1, 2]);
+        assert_eq!(indices(&points), [2, 40]);
+
+        points.apply(SelectionOp::Replace, [5]);
+        assert_eq!(indices(&points), [5]);
+
+        points.apply(SelectionOp::Remove, [5]);
+        assert!(points.is_empty());
+        assert!(!points.contains(5));
+    }
+
+    #[test]
+    fn invert_stops_at_the_last_point() {
+        let mut points = selection([0, 33]);
+        points.invert(35);
+        let expected: Vec<u32> = (1..35).filter(|&index| index != 33).collect();
+        assert_eq!(indices(&points), expected);
+
+        let mut points = PointSelection::default();
+        points.invert(64);
+        assert_eq!(points.len(), 64);
+        points.invert(64);
+        assert!(points.is_empty());
+    }
+
+    #[test]
+    fn removed_points_keep_the_selection_of_the_moved_points() {
+        // Like `PointCloudAsset::remove_points` on 6 points: 5 moves to 2, and 4 to 1.
+        let mut points = selection([2, 4, 5]);
+        points.remove_points(6, [1, 2, 9]);
+        assert_eq!(indices(&points), [1, 2]);
+
+        // Removing the last point only deselects it.
+        let mut points = selection([0, 3]);
+        points.remove_points(4, [3]);
+        assert_eq!(indices(&points), [0]);
+    }
+
+    /// A camera at `z = 10` looking at the origin, with a 100 by 100 viewport.
+    fn camera() -> (Camera, GlobalTransform) {
+        let mut world = World::new();
+        world.init_resource::<Assets<Image>>();
+        world.init_resource::<ManualTextureViews>();
+        world.init_resource::<Events<WindowCreated>>();
+        world.init_resource::<Events<WindowResized>>();
+        world.init_resource::<Events<AssetEvent<Image>>>();
+        let window = world
+            .spawn(Window {
+                resolution: WindowResolution::new(100.0, 100.0),
+                ..default()
+            })
+            .id();
+        let transform = Transform::from_xyz(0.0, 0.0, 10.0).looking_at(Vec3::ZERO, Vec3::Y);
+        let camera = world
+            .spawn(Camera3dBundle {
+                camera: Camera {
+                    target: RenderTarget::Window(WindowRef::Entity(window)),
+                    ..default()
+                },
+                transform,
+                ..default()
+            })
+            .id();
+        world.run_system_once(camera_system::<Projection>);
+        (
+            world.get::<Camera>(camera).unwrap().clone(),
+            transform.into(),
+        )
+    }
+
+    /// A point left of, right of, above and behind the camera.
+    fn asset() -> PointCloudAsset {
+        let mut mesh = Mesh::new(PrimitiveTopology::PointList);
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_POSITION,
+            vec![
+                [-1.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 0.0, 20.0],
+            ],
+        );
+        PointCloudAsset::new(mesh)
+    }
+
+    #[test]
+    fn points_in_a_viewport_polygon() {
+        let (camera, camera_transform) = camera();
+        let asset = asset();
+        let transform = GlobalTransform::IDENTITY;
+
+        // The top left half of the viewport, the viewport y axis points down.
+        let triangle = [Vec2::ZERO, Vec2::new(100.0, 0.0), Vec2::new(0.0, 100.0)];
+        let points =
+            points_in_viewport_polygon(&asset, &transform, &camera, &camera_transform, &triangle);
+        assert_eq!(points, [0, 2]);
+
+        // Every point in front of the camera.
+        let viewport = [
+            Vec2::ZERO,
+            Vec2::new(100.0, 0.0),
+            Vec2::splat(100.0),
+            Vec2::new(0.0, 100.0),
+        ];
+        let points =
+            points_in_viewport_polygon(&asset, &transform, &camera, &camera_transform, &viewport);
+        assert_eq!(points, [0, 1, 2]);
+
+        // Too few vertices to enclose anything.
+        let points = points_in_viewport_polygon(
+            &asset,
+            &transform,
+            &camera,
+            &camera_transform,
+            &viewport[..2],
+        );
+        assert!(points.is_empty());
+    }
+
+    #[test]
+    fn points_in_a_viewport_rect() {
+        let (camera, camera_transform) = camera();
+        let asset = asset();
+
+        let right_half = Rect::new(55.0, 0.0, 100.0, 100.0);
+        let points = points_in_viewport_rect(
+            &asset,
+            &GlobalTransform::IDENTITY,
+            &camera,
+            &camera_transform,
+            right_half,
+        );
+        assert_eq!(points, [1]);
+
+        // Moved left, the right point is in the middle of the viewport.
+        let points = points_in_viewport_rect(
+            &asset,
+            &GlobalTransform::from_xyz(-1.0, 0.0, 0.0),
+            &camera,
+            &camera_transform,
+            right_half,
+        );
+        assert!(points.is_empty());
+    }
+}
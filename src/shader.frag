@@ -2,22 +2,190 @@
 
 #import bevy_render::view::View
 
+#ifndef SHADOW_PASS
 layout(location = 0) out vec4 o_Target;
 layout(location = 1) out float o_Depth;
+#endif
 layout(location = 0) in vec2 in_Point_Location;
 layout(location = 1) in vec3 in_Color;
+#ifdef LIT
+layout(location = 4) in vec3 in_Lit_Position;
+layout(location = 5) in vec3 in_Normal;
+#endif
+#ifdef TRANSPARENT
+layout(location = 6) in float in_Alpha;
+#endif
 
 layout(set = 0, binding = 0) uniform View view;
 layout(set = 2, binding = 0) uniform Model {
     mat4 model_transform;
     float point_size;
+    uint first_clipping_plane;
+    uint num_clipping_planes;
+    uint first_clipping_volume;
+    uint num_clipping_volumes;
+    uint first_selection_word;
+    uint num_selection_words;
+    vec4 selection_color;
+    vec2 scalar_range;
+    uint num_ramp_colors;
+    vec4 ramp_colors[8];
+    float stream_time;
+    float stream_max_age;
+    float stream_fade_duration;
+    uint stream_first;
+    uint stream_capacity;
+    float opacity;
+    uint picking_id;
 };
 
+#ifdef LIT
+struct PointCloudLight {
+    // The direction towards a directional light, or the position of a point light in `w = 1`.
+    vec4 position;
+    vec4 color_inverse_square_range;
+    vec3 spot_direction;
+    float spot_scale;
+    float spot_offset;
+};
+layout(std430, set = 0, binding = 5) readonly buffer PointCloudLights {
+    vec4 ambient_color;
+    PointCloudLight[] lights;
+};
+
+const float PI = 3.141592653589793;
+
+// The range attenuation of Bevy's point lights.
+float distance_attenuation(float distance_square, float inverse_range_squared) {
+    float factor = distance_square * inverse_range_squared;
+    float smooth_factor = clamp(1.0 - factor * factor, 0.0, 1.0);
+    return smooth_factor * smooth_factor / max(distance_square, 0.0001);
+}
+
+#ifdef SHADOWS
+// Bevy's shadow maps of directional light cascades, followed by those of spot lights.
+layout(set = 3, binding = 0) uniform texture2DArray shadow_map_texture;
+layout(set = 3, binding = 1) uniform samplerShadow shadow_map_sampler;
+
+struct PointCloudShadowMap {
+    mat4 view_projection;
+    uint light;
+    uint layer;
+    float depth_bias;
+    float normal_bias;
+};
+// The cascades of a light are next to each other, from the nearest one.
+layout(std430, set = 3, binding = 2) readonly buffer PointCloudShadowMaps {
+    uint num_shadow_maps;
+    PointCloudShadowMap[] shadow_maps;
+};
+
+// How much of the light reaches the point, from the first of its shadow maps covering the point.
+float shadow(uint light, vec3 normal, vec3 light_direction, float distance_to_light) {
+    for (uint i = 0u; i < num_shadow_maps; i++) {
+        PointCloudShadowMap shadow_map = shadow_maps[i];
+        if (shadow_map.light != light) {
+            continue;
+        }
+        // The normal bias grows with the distance to spot lights, like their texels.
+        vec3 offset_position = in_Lit_Position
+            + light_direction * shadow_map.depth_bias
+            + normal * shadow_map.normal_bias * distance_to_light;
+        vec4 clip_position = shadow_map.view_projection * vec4(offset_position, 1.0);
+        vec3 ndc = clip_position.xyz / clip_position.w;
+        if (clip_position.w <= 0.0 || any(greaterThan(abs(ndc.xy), vec2(1.0))) || ndc.z < 0.0 || ndc.z > 1.0) {
+            // Outside of this cascade
+            continue;
+        }
+        vec2 uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+        return texture(
+            sampler2DArrayShadow(shadow_map_texture, shadow_map_sampler),
+            vec4(uv, float(shadow_map.layer), ndc.z)
+        );
+    }
+    return 1.0;
+}
+#endif
+
+// Lights the point like a diffuse material.
+vec3 shade(vec3 color) {
+    vec3 normal = normalize(in_Normal);
+    // Points have no back side, so they are lit from the side facing the camera.
+    if (dot(normal, view.world_position - in_Lit_Position) < 0.0) {
+        normal = -normal;
+    }
+    vec3 light = ambient_color.rgb;
+    for (uint i = 0u; i < uint(lights.length()); i++) {
+        PointCloudLight point_cloud_light = lights[i];
+        vec3 to_light = point_cloud_light.position.xyz;
+        float attenuation = 1.0;
+        float distance_to_light = 1.0;
+        if (point_cloud_light.position.w != 0.0) {
+            to_light -= in_Lit_Position;
+            distance_to_light = length(to_light);
+            attenuation = distance_attenuation(
+                dot(to_light, to_light),
+                point_cloud_light.color_inverse_square_range.w
+            );
+        }
+        vec3 light_direction = normalize(to_light);
+        float cone = clamp(
+            dot(point_cloud_light.spot_direction, -light_direction) * point_cloud_light.spot_scale
+                + point_cloud_light.spot_offset,
+            0.0,
+            1.0
+        );
+        attenuation *= cone * cone;
+        #ifdef SHADOWS
+        attenuation *= shadow(i, normal, light_direction, distance_to_light);
+        #endif
+        float n_dot_l = max(dot(normal, light_direction), 0.0);
+        light += point_cloud_light.color_inverse_square_range.rgb * attenuation * n_dot_l / PI;
+    }
+    return color * light;
+}
+#endif
+
 void main()
 {
     vec2 uv = in_Point_Location * 2.0 - 1.0;
+    #ifdef CIRCLE
+    // Outside of the disk of the point.
+    if (dot(uv, uv) > 1.0) {
+        discard;
+    }
+    #endif
+    #ifndef SHADOW_PASS
+    #ifdef LIT
+    vec3 color = shade(in_Color);
+    #else
+    vec3 color = in_Color;
+    #endif
+    #ifdef SPLAT_ACCUMULATE
+    // Points weigh less towards their edges, so overlapping points blend smoothly.
+    float weight = max(1.0 - dot(uv, uv), 0.001);
+    o_Target = vec4(color * weight, weight);
+    #else ifdef TRANSPARENT
+    o_Target = vec4(color, in_Alpha);
+    #else
+    o_Target = vec4(color, 1.0);
+    #endif
+
+    #ifdef ORIENTED_DISK
+    // The disk lies in the surface, so its depth needs no offset.
+    float depth_offset = 0.0;
+    #else
+    #ifdef PARABOLOID
+    float depth_offset = dot(uv, uv);
+    #else
     float depth_offset = sqrt(uv.x * uv.x + uv.y * uv.y);
-    o_Target = vec4(in_Color, 1.0);
+    #endif
+    #endif
+    #ifdef SPLAT_DEPTH
+    // Pushed back, so the points just behind the nearest ones are accumulated with them.
+    // The bits of the epsilon in point sizes, see `PointCloudRenderMode::HighQualitySplatting`.
+    depth_offset += uintBitsToFloat(#SPLAT_DEPTH_EPSILON_BITS);
+    #endif
 
 
     float depth = 1.0 / gl_FragCoord.w; // the world space depth
@@ -36,4 +204,5 @@ void main()
     float depth_output = z_near / offseted_depth;
     gl_FragDepth = depth_output;
     o_Depth = depth_output;
+    #endif
 }
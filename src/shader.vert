@@ -6,6 +6,17 @@ layout(location = 0) in vec2 in_Position_Point;
 
 layout(location = 0) out vec2 out_Point_Location;
 layout(location = 1) out vec3 out_Color;
+#ifdef PICKING
+layout(location = 2) flat out uint out_Point_Index;
+layout(location = 3) flat out vec3 out_World_Position;
+#endif
+#ifdef LIT
+layout(location = 4) out vec3 out_Lit_Position;
+layout(location = 5) out vec3 out_Normal;
+#endif
+#ifdef TRANSPARENT
+layout(location = 6) out float out_Alpha;
+#endif
 
 layout(set = 0, binding = 0) uniform View view;
 
@@ -14,24 +25,102 @@ struct ClippingPlane {
     vec3 unit_normal;
     float min_sdist;
     float max_sdist;
+    uint union_group;
+    uint highlight_mode;
+    float highlight_param;
+    vec4 highlight_color;
+};
+layout(std430, set = 0, binding = 1) readonly buffer ClippingPlanes {
+    ClippingPlane[] clipping_planes;
 };
-layout(set = 0, binding = 1) uniform ClippingPlanes {
-    ClippingPlane ranges[16];
-    uint num_ranges;
-} clipping_planes;
+
+const uint NO_UNION_GROUP = 0xffffffffu;
+
+const uint CLIPPING_HIGHLIGHT_DISCARD = 0u;
+const uint CLIPPING_HIGHLIGHT_TINT = 1u;
+const uint CLIPPING_HIGHLIGHT_DIM = 2u;
+const uint CLIPPING_HIGHLIGHT_BAND = 3u;
+
+struct ClippingVolume {
+    mat4 world_to_local;
+    uint kind;
+    uint exclude;
+    uint first_element;
+    uint num_elements;
+    vec4 params;
+};
+layout(std430, set = 0, binding = 2) readonly buffer ClippingVolumes {
+    ClippingVolume[] clipping_volumes;
+};
+
+// Half spaces of polytopes, and vertices of polygons in `xy`.
+layout(std430, set = 0, binding = 3) readonly buffer ClippingElements {
+    vec4[] clipping_elements;
+};
+
+// Bitmasks of selected points, see `PointSelection`.
+layout(std430, set = 0, binding = 4) readonly buffer PointSelections {
+    uint[] selection_words;
+};
+
+const uint CLIPPING_VOLUME_BOX = 0u;
+const uint CLIPPING_VOLUME_SPHERE = 1u;
+const uint CLIPPING_VOLUME_CYLINDER = 2u;
+const uint CLIPPING_VOLUME_POLYTOPE = 3u;
+const uint CLIPPING_VOLUME_POLYGON = 4u;
 
 layout(set = 2, binding = 0) uniform Model {
     mat4 model_transform;
     float point_size_world_space;
+    uint first_clipping_plane;
+    uint num_clipping_planes;
+    uint first_clipping_volume;
+    uint num_clipping_volumes;
+    uint first_selection_word;
+    uint num_selection_words;
+    vec4 selection_color;
+    vec2 scalar_range;
+    uint num_ramp_colors;
+    vec4 ramp_colors[8];
+    float stream_time;
+    float stream_max_age;
+    float stream_fade_duration;
+    uint stream_first;
+    uint stream_capacity;
+    float opacity;
+    uint picking_id;
 };
 
 struct PointOffset {
     float position_x;
     float position_y;
     float position_z;
+    #ifdef ANIMATED_ATTRIBUTES
+    // Offset from the colour of the point.
+    float color_r;
+    float color_g;
+    float color_b;
+    float scalar;
+    #endif
 };
 
 #ifdef ANIMATED
+#ifdef RESIDENT_FRAMES
+// Every frame of the animation, after a frame of zero offsets.
+layout(std430, set = 1, binding = 1) readonly buffer AnimationFrames {
+    PointOffset[] frame_offsets;
+};
+
+// The frames to interpolate between, as indices into `frame_offsets`.
+layout(std430, set = 1, binding = 2) readonly buffer AnimationFrameIndices {
+    float interpolation;
+    uint num_frame_points;
+    uint prev_frame;
+    uint next_frame;
+    uint before_frame;
+    uint after_frame;
+};
+#else
 layout(std430, set = 1, binding = 1) readonly buffer AnimationOffset {
     float _old_interpolation;
     PointOffset[] prev_offsets;
@@ -41,6 +130,20 @@ layout(std430, set = 1, binding = 2) readonly buffer AnimationOffset {
     float interpolation;
     PointOffset[] next_offsets;
 };
+
+#ifdef CATMULL_ROM
+// The frames before `prev_offsets` and after `next_offsets`.
+layout(std430, set = 1, binding = 3) readonly buffer AnimationOffsetBefore {
+    float _before_interpolation;
+    PointOffset[] before_offsets;
+};
+
+layout(std430, set = 1, binding = 4) readonly buffer AnimationOffsetAfter {
+    float _after_interpolation;
+    PointOffset[] after_offsets;
+};
+#endif
+#endif
 #endif
 
 struct Point {
@@ -52,6 +155,18 @@ struct Point {
     float color_g;
     float color_b;
     #endif
+    #ifdef NORMALS
+    float normal_x;
+    float normal_y;
+    float normal_z;
+    #endif
+    #ifdef ALPHA
+    float alpha;
+    #endif
+    #ifdef STREAMING
+    // When the point was pushed, see `StreamingPointCloud`.
+    float time;
+    #endif
 };
 
 layout(std430, set = 1, binding = 0) readonly buffer Asset {
@@ -63,41 +178,258 @@ void discard_vertex() {
     gl_Position = vec4(nan);
 }
 
+bool is_inside_clipping_polygon(ClippingVolume volume, vec4 polygonPos4) {
+    if (polygonPos4.w <= 0.0) {
+        // Behind the camera of a viewport polygon.
+        return false;
+    }
+    vec2 p = polygonPos4.xy / polygonPos4.w;
+
+    // Even-odd rule: count the edges crossed by a ray going towards +X.
+    bool inside = false;
+    uint j = volume.num_elements - 1u;
+    for (uint i = 0; i < volume.num_elements; i++) {
+        vec2 a = clipping_elements[volume.first_element + i].xy;
+        vec2 b = clipping_elements[volume.first_element + j].xy;
+        if ((a.y > p.y) != (b.y > p.y) && p.x < (b.x - a.x) * (p.y - a.y) / (b.y - a.y) + a.x) {
+            inside = !inside;
+        }
+        j = i;
+    }
+    return inside;
+}
+
+bool is_inside_clipping_volume(ClippingVolume volume, vec3 worldPos) {
+    vec4 localPos4 = volume.world_to_local * vec4(worldPos, 1.0);
+    if (volume.kind == CLIPPING_VOLUME_POLYGON) {
+        return volume.num_elements >= 3u && is_inside_clipping_polygon(volume, localPos4);
+    }
+    vec3 localPos = localPos4.xyz / localPos4.w;
+    if (volume.kind == CLIPPING_VOLUME_BOX) {
+        return all(lessThanEqual(abs(localPos), volume.params.xyz));
+    } else if (volume.kind == CLIPPING_VOLUME_SPHERE) {
+        return length(localPos) <= volume.params.x;
+    } else if (volume.kind == CLIPPING_VOLUME_CYLINDER) {
+        return length(localPos.xz) <= volume.params.x && abs(localPos.y) <= volume.params.y;
+    } else {
+        for (uint i = 0; i < volume.num_elements; i++) {
+            vec4 half_space = clipping_elements[volume.first_element + i];
+            if (dot(half_space.xyz, localPos) > half_space.w) {
+                return false;
+            }
+        }
+        return true;
+    }
+}
+
+// How far outside of the range the point is, negative if it is inside.
+float distance_outside_clipping_plane(ClippingPlane range, vec3 worldPos) {
+    float sdist_to_plane = dot(worldPos - range.origin, range.unit_normal);
+    return max(range.min_sdist - sdist_to_plane, sdist_to_plane - range.max_sdist);
+}
+
+// Applies the highlight of a range to a point it clips. Returns false if the point is hidden.
+bool highlight_clipped_point(ClippingPlane range, float distance_outside, inout vec3 color) {
+    if (range.highlight_mode == CLIPPING_HIGHLIGHT_TINT) {
+        color = mix(color, range.highlight_color.rgb, range.highlight_param);
+        return true;
+    } else if (range.highlight_mode == CLIPPING_HIGHLIGHT_DIM) {
+        color *= range.highlight_param;
+        return true;
+    } else if (range.highlight_mode == CLIPPING_HIGHLIGHT_BAND && distance_outside <= range.highlight_param) {
+        color = range.highlight_color.rgb;
+        return true;
+    }
+    return false;
+}
+
+// Ranges without a union group must all contain the point, while a union group only needs
+// one of its ranges to contain it. Ranges of the same union group are next to each other.
+// When a union group clips the point, the highlight of its nearest range is used.
+bool apply_clipping_planes(vec3 worldPos, inout vec3 color) {
+    uint current_group = NO_UNION_GROUP;
+    bool current_group_visible = true;
+    uint nearest_range = 0u;
+    float nearest_distance = 0.0;
+    for (uint i = first_clipping_plane; i < first_clipping_plane + num_clipping_planes; i++) {
+        ClippingPlane range = clipping_planes[i];
+        float distance_outside = distance_outside_clipping_plane(range, worldPos);
+        bool inside = distance_outside <= 0.0;
+        if (range.union_group == NO_UNION_GROUP) {
+            if (!inside && !highlight_clipped_point(range, distance_outside, color)) {
+                return false;
+            }
+            continue;
+        }
+        if (range.union_group != current_group) {
+            if (!current_group_visible && !highlight_clipped_point(clipping_planes[nearest_range], nearest_distance, color)) {
+                return false;
+            }
+            current_group = range.union_group;
+            current_group_visible = false;
+            nearest_range = i;
+            nearest_distance = distance_outside;
+        }
+        current_group_visible = current_group_visible || inside;
+        if (distance_outside < nearest_distance) {
+            nearest_range = i;
+            nearest_distance = distance_outside;
+        }
+    }
+    return current_group_visible || highlight_clipped_point(clipping_planes[nearest_range], nearest_distance, color);
+}
+
+bool is_point_selected(uint index) {
+    uint word = index / 32u;
+    if (word >= num_selection_words) {
+        return false;
+    }
+    return (selection_words[first_selection_word + word] & (1u << (index % 32u))) != 0u;
+}
+
+// Clip any points that falls inside of an excluding volume or outside of an including one.
+bool is_visible_through_clipping_volumes(vec3 worldPos) {
+    for (uint i = first_clipping_volume; i < first_clipping_volume + num_clipping_volumes; i++) {
+        ClippingVolume volume = clipping_volumes[i];
+        if (is_inside_clipping_volume(volume, worldPos) == (volume.exclude != 0u)) {
+            return false;
+        }
+    }
+    return true;
+}
+
+#ifdef ANIMATED
+// Interpolates between the `prev` and `next` frames, using the frames `before` and `after`
+// them for Catmull-Rom splines.
+vec4 interpolate_frames(vec4 before, vec4 prev, vec4 next, vec4 after, float t) {
+    #ifdef CATMULL_ROM
+    return 0.5 * (
+        2.0 * prev
+        + (next - before) * t
+        + (2.0 * before - 5.0 * prev + 4.0 * next - after) * t * t
+        + (3.0 * prev - before - 3.0 * next + after) * t * t * t
+    );
+    #else
+    return mix(prev, next, t);
+    #endif
+}
+
+vec4 offset_position(PointOffset offset) {
+    return vec4(offset.position_x, offset.position_y, offset.position_z, 0.0);
+}
+#endif
+
+#ifdef ANIMATED_ATTRIBUTES
+// The color offset and scalar of a point.
+vec4 offset_attributes(PointOffset offset) {
+    return vec4(offset.color_r, offset.color_g, offset.color_b, offset.scalar);
+}
+
+vec4 sample_color_ramp(float value) {
+    float range = scalar_range.y - scalar_range.x;
+    float t = range != 0.0 ? clamp((value - scalar_range.x) / range, 0.0, 1.0) : 0.0;
+    if (num_ramp_colors == 1u) {
+        return ramp_colors[0];
+    }
+    float position = t * float(num_ramp_colors - 1u);
+    uint i = min(uint(position), num_ramp_colors - 2u);
+    return mix(ramp_colors[i], ramp_colors[i + 1u], position - float(i));
+}
+#endif
+
 void main() {
+    #ifdef STREAMING
+    // Streaming points are drawn from the oldest one, around the ring buffer.
+    Point p = points[(stream_first + uint(gl_InstanceIndex)) % stream_capacity];
+    float age = stream_time - p.time;
+    if (age > stream_max_age) {
+        discard_vertex();
+        return;
+    }
+    #else
     Point p = points[gl_InstanceIndex];
+    #endif
 
     vec3 in_Pos = vec3(p.position_x, p.position_y, p.position_z);
     #ifdef ANIMATED
+    #ifdef RESIDENT_FRAMES
+    uint point_index = uint(gl_InstanceIndex);
+    PointOffset prev_offset = frame_offsets[prev_frame * num_frame_points + point_index];
+    PointOffset next_offset = frame_offsets[next_frame * num_frame_points + point_index];
+    #ifdef CATMULL_ROM
+    PointOffset before_offset = frame_offsets[before_frame * num_frame_points + point_index];
+    PointOffset after_offset = frame_offsets[after_frame * num_frame_points + point_index];
+    #endif
+    #else
     PointOffset prev_offset = prev_offsets[gl_InstanceIndex];
     PointOffset next_offset = next_offsets[gl_InstanceIndex];
-    vec3 prev = vec3(prev_offset.position_x, prev_offset.position_y, prev_offset.position_z);
-    vec3 next = vec3(next_offset.position_x, next_offset.position_y, next_offset.position_z);
-    vec3 interpolated = prev + (next - prev) * interpolation;
-    in_Pos += interpolated;
+    #ifdef CATMULL_ROM
+    PointOffset before_offset = before_offsets[gl_InstanceIndex];
+    PointOffset after_offset = after_offsets[gl_InstanceIndex];
+    #endif
+    #endif
+    #ifndef CATMULL_ROM
+    // Unused by linear interpolation.
+    PointOffset before_offset = prev_offset;
+    PointOffset after_offset = next_offset;
+    #endif
+    in_Pos += interpolate_frames(
+        offset_position(before_offset),
+        offset_position(prev_offset),
+        offset_position(next_offset),
+        offset_position(after_offset),
+        interpolation
+    ).xyz;
+    #ifdef ANIMATED_ATTRIBUTES
+    vec4 attributes = interpolate_frames(
+        offset_attributes(before_offset),
+        offset_attributes(prev_offset),
+        offset_attributes(next_offset),
+        offset_attributes(after_offset),
+        interpolation
+    );
+    #endif
     #endif
 
     vec4 out_Pos = view.view_proj * model_transform * vec4(in_Pos, 1.0);
-    if (clipping_planes.num_ranges > 0u) {
-        vec4 worldPos4 = model_transform * vec4(in_Pos, 1.0);
-        vec3 worldPos = worldPos4.xyz / worldPos4.w;
-
-        // Clip any points that falls out of the allowed ranges.
-        for (uint i = 0; i < clipping_planes.num_ranges; i++) {
-            ClippingPlane range = clipping_planes.ranges[i];
-            float sdist_to_plane = dot(worldPos - range.origin, range.unit_normal);
-            if (sdist_to_plane < range.min_sdist || sdist_to_plane > range.max_sdist) {
-                // DISCARD point
-                discard_vertex();
-                return;
-            }
-        }
-    }
     #ifdef COLORED
     out_Color = vec3(p.color_r, p.color_g, p.color_b);
     #else
     out_Color = vec3(p.position_x % 1.0, p.position_y % 1.0, p.position_z % 1.0);
     #endif
+    #ifdef ANIMATED_ATTRIBUTES
+    out_Color += attributes.rgb;
+    if (num_ramp_colors > 0u) {
+        vec4 ramp_color = sample_color_ramp(attributes.a);
+        out_Color = mix(out_Color, ramp_color.rgb, ramp_color.a);
+    }
+    #endif
+
+    if (selection_color.a > 0.0 && is_point_selected(uint(gl_InstanceIndex))) {
+        out_Color = mix(out_Color, selection_color.rgb, selection_color.a);
+    }
+
+    if (num_clipping_planes > 0u || num_clipping_volumes > 0u) {
+        vec4 worldPos4 = model_transform * vec4(in_Pos, 1.0);
+        vec3 worldPos = worldPos4.xyz / worldPos4.w;
+        if (!apply_clipping_planes(worldPos, out_Color) || !is_visible_through_clipping_volumes(worldPos)) {
+            // DISCARD point
+            discard_vertex();
+            return;
+        }
+    }
 
+    #ifdef TRANSPARENT
+    out_Alpha = opacity;
+    #ifdef ALPHA
+    out_Alpha *= p.alpha;
+    #endif
+    if (out_Alpha <= 0.0) {
+        // Fully transparent
+        discard_vertex();
+        return;
+    }
+    #endif
 
     vec2 point_size = vec2(0.0, 0.0);
     if (view.projection[2][3] == -1.0) {
@@ -113,7 +445,45 @@ void main() {
         point_size = vec2(point_size_world_space / max_scale);
     }
     point_size.y *= view.viewport.z / view.viewport.w;
+    #ifdef STREAMING
+    if (stream_fade_duration > 0.0) {
+        point_size *= clamp((stream_max_age - age) / stream_fade_duration, 0.0, 1.0);
+    }
+    #endif
 
     out_Point_Location = in_Position_Point;
+    #ifdef LIT
+    vec4 lit_position = model_transform * vec4(in_Pos, 1.0);
+    out_Lit_Position = lit_position.xyz / lit_position.w;
+    // Only exact for transforms without non-uniform scaling.
+    out_Normal = mat3(model_transform) * vec3(p.normal_x, p.normal_y, p.normal_z);
+    #endif
+    #ifdef PICKING
+    out_Point_Index = uint(gl_InstanceIndex);
+    vec4 world_position = model_transform * vec4(in_Pos, 1.0);
+    out_World_Position = world_position.xyz / world_position.w;
+    #endif
+    #ifdef ORIENTED_DISK
+    // The quad lies in the plane of the point's normal, centred on the point, with the width
+    // of the screen aligned quads.
+    vec3 disk_normal = normalize(mat3(model_transform) * vec3(p.normal_x, p.normal_y, p.normal_z));
+    vec3 disk_up = abs(disk_normal.y) < 0.99 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+    vec3 disk_tangent = normalize(cross(disk_normal, disk_up));
+    vec3 disk_bitangent = cross(disk_normal, disk_tangent);
+    vec4 disk_center = model_transform * vec4(in_Pos, 1.0);
+    vec2 disk_corner = (in_Position_Point * 2.0 - 1.0) * 0.25 * point_size_world_space;
+    vec3 disk_position = disk_center.xyz / disk_center.w
+        + disk_tangent * disk_corner.x
+        + disk_bitangent * disk_corner.y;
+    gl_Position = view.view_proj * vec4(disk_position, 1.0);
+    #else
     gl_Position = out_Pos + vec4(in_Position_Point * point_size, 0.0, 0.0);
+    #endif
+    #ifdef SHADOW_PASS
+    if (view.projection[2][3] != -1.0) {
+        // Points behind the near plane of a directional light still cast shadows, like
+        // Bevy's DEPTH_CLAMP_ORTHO.
+        gl_Position.z = min(gl_Position.z, gl_Position.w);
+    }
+    #endif
 }
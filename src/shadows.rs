@@ -0,0 +1,326 @@
+use bevy::{
+    ecs::query::QueryItem,
+    pbr::{LightEntity, ShadowView, ViewLightEntities, ViewShadowBindings},
+    prelude::*,
+    render::{
+        extract_component::{DynamicUniformIndex, ExtractComponent},
+        render_asset::RenderAssets,
+        render_graph::{NodeRunError, RenderGraphContext, ViewNode},
+        render_resource::*,
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        view::{ExtractedView, ViewUniformOffset},
+    },
+    utils::HashMap,
+};
+
+use crate::{
+    pipeline::{PointCloudBindGroup, PointCloudPipeline, PointCloudPipelineKey},
+    render::PointCloudDrawData,
+    PlaybackControls, PointCloudAnimationInstances, PointCloudAsset, PointCloudShading,
+    PointCloudUniform, PointShape, PotreePointCloud,
+};
+
+/// Makes a [`PotreePointCloud`] cast shadows into the shadow maps of Bevy's
+/// [`DirectionalLight`]s and [`SpotLight`]s, with the shape of its points.
+///
+/// Point light shadows aren't supported, and the shadows need Bevy's `PbrPlugin`.
+#[derive(Clone, Copy, Component, Debug, Default)]
+pub struct PointCloudShadowCaster;
+
+impl ExtractComponent for PointCloudShadowCaster {
+    type Query = &'static Self;
+    type Filter = With<PotreePointCloud>;
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::Query>) -> Option<Self> {
+        Some(*item)
+    }
+}
+
+/// A light with shadows, see [`PointCloudShadowLights`].
+pub(crate) struct PointCloudShadowLight {
+    /// The index of the light in the point cloud lights buffer.
+    pub index: u32,
+    pub depth_bias: f32,
+    /// In shadow map texels.
+    pub normal_bias: f32,
+}
+
+/// The lights with shadows, by their entity, filled when the lights are extracted.
+#[derive(Resource, Default)]
+pub(crate) struct PointCloudShadowLights(pub HashMap<Entity, PointCloudShadowLight>);
+
+#[derive(Clone, Debug, Default, ShaderType)]
+pub(crate) struct GpuPointCloudShadowMap {
+    /// From world space to the clip space of the shadow map.
+    pub view_projection: Mat4,
+    /// The index of the light in the point cloud lights buffer.
+    pub light: u32,
+    /// The layer of Bevy's directional shadow map texture, which also holds the maps of spot
+    /// lights.
+    pub layer: u32,
+    /// How far points are moved towards the light before sampling, in world units.
+    pub depth_bias: f32,
+    /// How far points are moved along their normal before sampling, in world units, per
+    /// unit of distance to spot lights.
+    pub normal_bias: f32,
+}
+
+#[derive(Clone, Debug, Default, ShaderType)]
+pub(crate) struct GpuPointCloudShadowMaps {
+    pub num_shadow_maps: u32,
+    #[size(runtime)]
+    pub shadow_maps: Vec<GpuPointCloudShadowMap>,
+}
+
+/// The shadow maps of each view, which depend on the view through the cascades of
+/// directional lights.
+#[derive(Resource, Default)]
+pub struct PointCloudShadowMapBuffers(HashMap<Entity, StorageBuffer<GpuPointCloudShadowMaps>>);
+
+pub(crate) fn prepare_point_cloud_shadow_maps(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    views: Query<(Entity, &ViewLightEntities), With<ViewShadowBindings>>,
+    light_views: Query<(&ExtractedView, &LightEntity)>,
+    shadow_lights: Res<PointCloudShadowLights>,
+    mut buffers: ResMut<PointCloudShadowMapBuffers>,
+) {
+    buffers.0.retain(|entity, _| views.contains(*entity));
+    for (entity, view_lights) in &views {
+        // Bevy stores the maps of spot lights after the cascades of directional lights.
+        let num_cascades = light_views
+            .iter_many(&view_lights.lights)
+            .filter(|(_, light)| matches!(light, LightEntity::Directional { .. }))
+            .count();
+        let mut num_previous_cascades = 0;
+        let mut num_spot_lights = 0;
+        let mut shadow_maps = Vec::new();
+        for (view, light) in light_views.iter_many(&view_lights.lights) {
+            // In the order of Bevy's shadow views, which take the layers one after another.
+            let (light_entity, layer) = match *light {
+                LightEntity::Directional { light_entity, .. } => {
+                    num_previous_cascades += 1;
+                    (light_entity, num_previous_cascades - 1)
+                }
+                LightEntity::Spot { light_entity } => {
+                    num_spot_lights += 1;
+                    (light_entity, num_cascades + num_spot_lights - 1)
+                }
+                LightEntity::Point { .. } => continue,
+            };
+            let Some(shadow_light) = shadow_lights.0.get(&light_entity) else {
+                continue;
+            };
+            // The width of a texel, at a distance of one from spot lights.
+            let texel_size = 2.0 / view.projection.x_axis.x / view.viewport.z as f32;
+            shadow_maps.push(GpuPointCloudShadowMap {
+                view_projection: view
+                    .view_projection
+                    .unwrap_or_else(|| view.projection * view.transform.compute_matrix().inverse()),
+                light: shadow_light.index,
+                layer: layer as u32,
+                depth_bias: shadow_light.depth_bias,
+                normal_bias: shadow_light.normal_bias * texel_size,
+            });
+        }
+
+        let buffer = buffers.0.entry(entity).or_default();
+        *buffer.get_mut() = GpuPointCloudShadowMaps {
+            num_shadow_maps: shadow_maps.len() as u32,
+            shadow_maps,
+        };
+        buffer.write_buffer(&render_device, &render_queue);
+    }
+}
+
+/// The shadow maps of a view, for point clouds lit with [`PointCloudShading::Lit`].
+#[derive(Component)]
+pub struct PointCloudShadowBindGroup(pub BindGroup);
+
+pub(crate) fn queue_point_cloud_shadow_bind_groups(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    pipeline: Res<PointCloudPipeline>,
+    buffers: Res<PointCloudShadowMapBuffers>,
+    views: Query<(Entity, &ViewShadowBindings)>,
+) {
+    for (entity, shadow_bindings) in &views {
+        let Some(binding) = buffers.0.get(&entity).and_then(StorageBuffer::binding) else {
+            continue;
+        };
+        let bind_group = render_device.create_bind_group(
+            "point_cloud_shadow_bind_group",
+            &pipeline.shadow_layout,
+            &BindGroupEntries::sequential((
+                &shadow_bindings.directional_light_depth_texture_view,
+                &pipeline.shadow_sampler,
+                binding,
+            )),
+        );
+        commands
+            .entity(entity)
+            .insert(PointCloudShadowBindGroup(bind_group));
+    }
+}
+
+/// The point clouds drawn into every shadow map, with the pipelines of
+/// [`PointCloudPipelineKey::shadow_pass`].
+#[derive(Resource, Default)]
+pub struct PointCloudShadowCasters {
+    pub list: Vec<PointCloudDrawData>,
+}
+
+#[allow(clippy::type_complexity)]
+pub(crate) fn queue_point_cloud_shadow_casters(
+    pipeline: Res<PointCloudPipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<PointCloudPipeline>>,
+    cache: Res<PipelineCache>,
+    casters: Query<
+        (
+            Entity,
+            &Handle<PointCloudAsset>,
+            Option<&PlaybackControls>,
+            Option<&PointShape>,
+        ),
+        With<PointCloudShadowCaster>,
+    >,
+    point_clouds: Res<RenderAssets<PointCloudAsset>>,
+    mut shadow_casters: ResMut<PointCloudShadowCasters>,
+) {
+    shadow_casters.list.clear();
+    for (entity, handle, playback, shape) in &casters {
+        let Some(asset) = point_clouds.get(handle) else {
+            continue;
+        };
+        let key = PointCloudPipelineKey {
+            shadow_pass: true,
+            msaa: 1,
+            ..asset.pipeline_key(
+                playback.copied().unwrap_or_default(),
+                PointCloudShading::Unlit,
+                shape.copied().unwrap_or_default(),
+            )
+        };
+        shadow_casters.list.push(PointCloudDrawData {
+            entity,
+            pipeline_id: pipelines.specialize(&cache, &pipeline, key),
+            picking_pipeline_id: None,
+            splat_pipeline_ids: None,
+            transparent: false,
+        });
+    }
+}
+
+/// Renders the depth of the [`PointCloudShadowCaster`]s into the directional and spot light
+/// shadow maps of a view, after Bevy's shadow pass cleared and filled them.
+pub struct PointCloudShadowNode {
+    light_view_query: QueryState<(
+        &'static ShadowView,
+        &'static LightEntity,
+        &'static ViewUniformOffset,
+    )>,
+    entity_query: QueryState<(
+        &'static Handle<PointCloudAsset>,
+        &'static DynamicUniformIndex<PointCloudUniform>,
+    )>,
+}
+
+impl PointCloudShadowNode {
+    pub const NAME: &'static str = "point_cloud_shadow_node";
+}
+
+impl FromWorld for PointCloudShadowNode {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            light_view_query: world.query_filtered(),
+            entity_query: world.query_filtered(),
+        }
+    }
+}
+
+impl ViewNode for PointCloudShadowNode {
+    type ViewQuery = &'static ViewLightEntities;
+
+    fn update(&mut self, world: &mut World) {
+        self.light_view_query.update_archetypes(world);
+        self.entity_query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        view_lights: QueryItem<Self::ViewQuery>,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let shadow_casters = world.resource::<PointCloudShadowCasters>();
+        if shadow_casters.list.is_empty() {
+            return Ok(());
+        }
+        let point_cloud_pipeline = world.resource::<PointCloudPipeline>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let render_assets = world.resource::<RenderAssets<PointCloudAsset>>();
+        let animation_instances = world.resource::<PointCloudAnimationInstances>();
+
+        let bind_groups = world.resource::<PointCloudBindGroup>();
+        let (Some(bind_group), Some(model_bind_group)) = (
+            bind_groups.bind_group.as_ref(),
+            bind_groups.model_bind_group.as_ref(),
+        ) else {
+            return Ok(());
+        };
+
+        for &light_view in &view_lights.lights {
+            let Ok((shadow_view, light, view_uniform_offset)) =
+                self.light_view_query.get_manual(world, light_view)
+            else {
+                continue;
+            };
+            if matches!(light, LightEntity::Point { .. }) {
+                continue;
+            }
+
+            let mut tracked_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("point_cloud_shadow"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: &shadow_view.depth_texture_view,
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Load,
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            tracked_pass.set_bind_group(0, bind_group, &[view_uniform_offset.offset]);
+            tracked_pass
+                .set_vertex_buffer(0, point_cloud_pipeline.instanced_point_quad.slice(0..32));
+            for draw_data in &shadow_casters.list {
+                let Some(pipeline) = pipeline_cache.get_render_pipeline(draw_data.pipeline_id)
+                else {
+                    continue;
+                };
+                let Ok((point_cloud_asset, dynamic_index)) =
+                    self.entity_query.get_manual(world, draw_data.entity)
+                else {
+                    continue;
+                };
+                let Some(point_cloud_asset) = render_assets.get(point_cloud_asset) else {
+                    continue;
+                };
+                let Some(point_cloud_bind_group) =
+                    animation_instances.bind_group(draw_data.entity, point_cloud_asset)
+                else {
+                    continue;
+                };
+
+                tracked_pass.set_render_pipeline(pipeline);
+                tracked_pass.set_bind_group(1, point_cloud_bind_group, &[]);
+                tracked_pass.set_bind_group(2, model_bind_group, &[dynamic_index.index()]);
+                tracked_pass.draw(0..4, 0..point_cloud_asset.num_points);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,32 @@
+use bevy::{ecs::query::QueryItem, prelude::*, render::extract_component::ExtractComponent};
+
+use crate::{PotreePointCloud, StreamingPointCloud};
+
+/// The shape each point of a point cloud is drawn with.
+#[derive(Clone, Copy, Component, Debug, Default, PartialEq, Eq, Hash)]
+pub enum PointShape {
+    /// A screen aligned square, pushed back towards its edges so overlapping points blend
+    /// into a surface.
+    #[default]
+    Square,
+    /// A screen aligned disk, with the depth of [`PointShape::Square`].
+    Circle,
+    /// A screen aligned disk, bulging towards the camera like a paraboloid, so neighbouring
+    /// points intersect more smoothly.
+    Paraboloid,
+    /// A disk lying in the surface of the point cloud, facing along the point's normal, like a
+    /// surfel. Needs the normals of
+    /// [`PointCloudAsset::estimate_normals`](crate::PointCloudAsset::estimate_normals), point
+    /// clouds without normals are drawn with [`PointShape::Circle`].
+    OrientedDisk,
+}
+
+impl ExtractComponent for PointShape {
+    type Query = &'static Self;
+    type Filter = Or<(With<PotreePointCloud>, With<StreamingPointCloud>)>;
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::Query>) -> Option<Self> {
+        Some(*item)
+    }
+}
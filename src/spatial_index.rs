@@ -0,0 +1,759 @@
+use std::sync::Arc;
+
+use bevy::{
+    ecs::system::SystemParam, math::Affine3A, prelude::*, render::mesh::VertexAttributeValues,
+    utils::HashMap,
+};
+
+use crate::{PointCloudAsset, PotreePointCloud};
+
+/// Maximum number of points stored in a leaf of a [`PointCloudSpatialIndex`].
+const LEAF_SIZE: usize = 16;
+
+#[derive(Clone, Debug)]
+struct KdNode {
+    min: Vec3,
+    max: Vec3,
+    /// Range of [`PointCloudSpatialIndex::points`] covered by this node.
+    start: u32,
+    end: u32,
+    /// Indices of the two child nodes, `None` for leaves.
+    children: Option<[u32; 2]>,
+}
+
+/// A KD-tree over the positions of a [`PointCloudAsset`], in the asset's local space.
+///
+/// Queries take the transform of the point cloud entity, and measure distances in world space.
+/// Animation offsets are not taken into account.
+#[derive(Clone, Debug)]
+pub struct PointCloudSpatialIndex {
+    nodes: Vec<KdNode>,
+    /// Local positions and their index in the asset, reordered so each node covers a contiguous range.
+    points: Vec<(Vec3, u32)>,
+}
+
+impl PointCloudSpatialIndex {
+    pub fn new(positions: impl IntoIterator<Item = Vec3>) -> Self {
+        let mut index = Self {
+            nodes: Vec::new(),
+            points: positions
+                .into_iter()
+                .enumerate()
+                .map(|(i, position)| (position, i as u32))
+                .collect(),
+        };
+        if !index.points.is_empty() {
+            index.build(0, index.points.len());
+        }
+        index
+    }
+
+    /// Builds the index from the asset's `Mesh::ATTRIBUTE_POSITION`, if it has one.
+    pub fn from_asset(asset: &PointCloudAsset) -> Option<Self> {
+        match asset.mesh().attribute(Mesh::ATTRIBUTE_POSITION)? {
+            VertexAttributeValues::Float32x3(positions) => {
+                Some(Self::new(positions.iter().copied().map(Vec3::from)))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    fn build(&mut self, start: usize, end: usize) -> u32 {
+        let (min, max) = self.points[start..end].iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), &(p, _)| (min.min(p), max.max(p)),
+        );
+        let node_index = self.nodes.len() as u32;
+        self.nodes.push(KdNode {
+            min,
+            max,
+            start: start as u32,
+            end: end as u32,
+            children: None,
+        });
+        if end - start > LEAF_SIZE {
+            // Split at the median of the longest axis.
+            let extent = max - min;
+            let axis = if extent.x >= extent.y && extent.x >= extent.z {
+                0
+            } else if extent.y >= extent.z {
+                1
+            } else {
+                2
+            };
+            let mid = (start + end) / 2;
+            self.points[start..end]
+                .select_nth_unstable_by(mid - start, |a, b| a.0[axis].total_cmp(&b.0[axis]));
+            let left = self.build(start, mid);
+            let right = self.build(mid, end);
+            self.nodes[node_index as usize].children = Some([left, right]);
+        }
+        node_index
+    }
+
+    /// Returns the index, world space position and distance along the ray of the first point hit,
+    /// treating points as spheres of `point_radius` in world space.
+    pub fn ray_cast(
+        &self,
+        transform: &GlobalTransform,
+        ray: Ray,
+        max_distance: f32,
+        point_radius: f32,
+    ) -> Option<(u32, Vec3, f32)> {
+        if self.is_empty() {
+            return None;
+        }
+        let local = LocalSpace::new(transform);
+        // An affine map preserves the ray parameter, so `t` stays a world space distance along
+        // the normalized world ray.
+        let direction = ray.direction.normalize();
+        let local_origin = local.world_to_local.transform_point3(ray.origin);
+        let local_direction = local.world_to_local.transform_vector3(direction);
+        let local_radius = point_radius * local.max_inverse_scale;
+
+        let mut best: Option<(u32, Vec3, f32)> = None;
+        let mut stack = vec![0u32];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index as usize];
+            let max_t = best.map_or(max_distance, |(_, _, t)| t);
+            let Some(t_enter) = ray_aabb(
+                local_origin,
+                local_direction,
+                node.min - local_radius,
+                node.max + local_radius,
+                max_t,
+            ) else {
+                continue;
+            };
+            if t_enter > max_t {
+                continue;
+            }
+            match node.children {
+                Some(children) => stack.extend(children),
+                None => {
+                    for &(position, index) in &self.points[node.start as usize..node.end as usize] {
+                        let world = local.local_to_world.transform_point3(position);
+                        if let Some(t) = ray_sphere(ray.origin, direction, world, point_radius) {
+                            if t <= best.map_or(max_distance, |(_, _, t)| t) {
+                                best = Some((index, world, t));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        best
+    }
+
+    /// Returns the indices and world space distances of the `k` points nearest to the world
+    /// space `point`, sorted by distance.
+    pub fn nearest(&self, transform: &GlobalTransform, point: Vec3, k: usize) -> Vec<(u32, f32)> {
+        let mut found: Vec<(u32, f32)> = Vec::with_capacity(k + 1);
+        if k == 0 || self.is_empty() {
+            return found;
+        }
+        let local = LocalSpace::new(transform);
+        let local_point = local.world_to_local.transform_point3(point);
+        self.search(
+            &local,
+            local_point,
+            point,
+            f32::INFINITY,
+            &mut |index, _, distance| {
+                if found.len() == k && distance >= found[k - 1].1 {
+                    return found[k - 1].1;
+                }
+                let at = found.partition_point(|&(_, d)| d <= distance);
+                found.insert(at, (index, distance));
+                found.truncate(k);
+                if found.len() == k {
+                    found[k - 1].1
+                } else {
+                    f32::INFINITY
+                }
+            },
+        );
+        found
+    }
+
+    /// Returns the index, world space position and distance of the point nearest to the world
+    /// space `point`, if it is within `max_distance`.
+    pub fn nearest_point(
+        &self,
+        transform: &GlobalTransform,
+        point: Vec3,
+        max_distance: f32,
+    ) -> Option<(u32, Vec3, f32)> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut best = None;
+        let local = LocalSpace::new(transform);
+        let local_point = local.world_to_local.transform_point3(point);
+        self.search(
+            &local,
+            local_point,
+            point,
+            max_distance,
+            &mut |index, world, distance| {
+                let bound = best.map_or(max_distance, |(_, _, d)| d);
+                if distance > bound {
+                    return bound;
+                }
+                best = Some((index, world, distance));
+                distance
+            },
+        );
+        best
+    }
+
+    /// Returns the indices and world space distances of the points within `radius` of the world
+    /// space `point`, in no particular order.
+    pub fn within_radius(
+        &self,
+        transform: &GlobalTransform,
+        point: Vec3,
+        radius: f32,
+    ) -> Vec<(u32, f32)> {
+        let mut found = Vec::new();
+        if self.is_empty() {
+            return found;
+        }
+        let local = LocalSpace::new(transform);
+        let local_point = local.world_to_local.transform_point3(point);
+        self.search(
+            &local,
+            local_point,
+            point,
+            radius,
+            &mut |index, _, distance| {
+                if distance <= radius {
+                    found.push((index, distance));
+                }
+                radius
+            },
+        );
+        found
+    }
+
+    /// Returns the indices and world space positions of the points inside the world space
+    /// axis aligned box from `min` to `max`, in no particular order. The bounds may be infinite.
+    pub fn within_box(
+        &self,
+        transform: &GlobalTransform,
+        min: Vec3,
+        max: Vec3,
+    ) -> Vec<(u32, Vec3)> {
+        let mut found = Vec::new();
+        if self.is_empty() {
+            return found;
+        }
+        let local_to_world = transform.affine();
+        let mut stack = vec![0u32];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index as usize];
+            let (node_min, node_max) = world_bounds(&local_to_world, node.min, node.max);
+            if node_max.cmplt(min).any() || node_min.cmpgt(max).any() {
+                continue;
+            }
+            match node.children {
+                Some(children) => stack.extend(children),
+                None => {
+                    for &(position, index) in &self.points[node.start as usize..node.end as usize] {
+                        let world = local_to_world.transform_point3(position);
+                        if world.cmpge(min).all() && world.cmple(max).all() {
+                            found.push((index, world));
+                        }
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// Visits the points in nodes that may be closer than `bound`, which is updated with the
+    /// value returned by `visit`.
+    fn search(
+        &self,
+        local: &LocalSpace,
+        local_point: Vec3,
+        world_point: Vec3,
+        mut bound: f32,
+        visit: &mut dyn FnMut(u32, Vec3, f32) -> f32,
+    ) {
+        let mut stack = vec![(0u32, 0.0f32)];
+        while let Some((node_index, lower_bound)) = stack.pop() {
+            if lower_bound > bound {
+                continue;
+            }
+            let node = &self.nodes[node_index as usize];
+            match node.children {
+                Some(children) => {
+                    let mut children = children.map(|child| {
+                        let child_node = &self.nodes[child as usize];
+                        let local_distance = (local_point.clamp(child_node.min, child_node.max)
+                            - local_point)
+                            .length();
+                        (child, local_distance * local.min_scale)
+                    });
+                    // Visit the nearest child first.
+                    if children[0].1 < children[1].1 {
+                        children.swap(0, 1);
+                    }
+                    stack.extend(children.into_iter().filter(|&(_, d)| d <= bound));
+                }
+                None => {
+                    for &(position, index) in &self.points[node.start as usize..node.end as usize] {
+                        let world = local.local_to_world.transform_point3(position);
+                        bound = visit(index, world, world.distance(world_point));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The transform of a point cloud, with the bounds needed to convert local distances to
+/// world distances under non-uniform scaling.
+struct LocalSpace {
+    local_to_world: Affine3A,
+    world_to_local: Affine3A,
+    /// Lower bound of the world length of a local unit vector.
+    min_scale: f32,
+    /// Upper bound of the local length of a world unit vector.
+    max_inverse_scale: f32,
+}
+
+impl LocalSpace {
+    fn new(transform: &GlobalTransform) -> Self {
+        let local_to_world = transform.affine();
+        let world_to_local = local_to_world.inverse();
+        let scale = transform.compute_transform().scale.abs();
+        Self {
+            local_to_world,
+            world_to_local,
+            min_scale: scale.min_element(),
+            max_inverse_scale: 1.0 / scale.min_element(),
+        }
+    }
+}
+
+/// Returns the world space bounds of a local space box.
+fn world_bounds(local_to_world: &Affine3A, min: Vec3, max: Vec3) -> (Vec3, Vec3) {
+    let center = local_to_world.transform_point3((min + max) * 0.5);
+    let half_extent = (max - min) * 0.5;
+    let matrix = local_to_world.matrix3;
+    let extent = Vec3::from(
+        matrix.x_axis.abs() * half_extent.x
+            + matrix.y_axis.abs() * half_extent.y
+            + matrix.z_axis.abs() * half_extent.z,
+    );
+    (center - extent, center + extent)
+}
+
+/// Returns the ray parameter where the ray enters the box, if it does before `max_t`.
+fn ray_aabb(origin: Vec3, direction: Vec3, min: Vec3, max: Vec3, max_t: f32) -> Option<f32> {
+    let inverse = direction.recip();
+    let t1 = (min - origin) * inverse;
+    let t2 = (max - origin) * inverse;
+    // NaNs from 0 * infinity are ignored by `min`/`max`.
+    let t_enter = t1.min(t2).max_element().max(0.0);
+    let t_exit = t1.max(t2).min_element().min(max_t);
+    (t_enter <= t_exit).then_some(t_enter)
+}
+
+/// Returns the first non-negative ray parameter where the normalized ray hits the sphere.
+fn ray_sphere(origin: Vec3, direction: Vec3, center: Vec3, radius: f32) -> Option<f32> {
+    let to_center = center - origin;
+    let t_closest = to_center.dot(direction);
+    let distance_squared = to_center.length_squared() - t_closest * t_closest;
+    let radius_squared = radius * radius;
+    if distance_squared > radius_squared {
+        return None;
+    }
+    let half_chord = (radius_squared - distance_squared).sqrt();
+    if t_closest + half_chord < 0.0 {
+        return None;
+    }
+    Some((t_closest - half_chord).max(0.0))
+}
+
+/// Spatial indices of loaded [`PointCloudAsset`]s, built on first use and dropped when the
+/// asset is modified or removed.
+#[derive(Resource, Default)]
+pub struct PointCloudSpatialIndices {
+    indices: HashMap<AssetId<PointCloudAsset>, Option<Arc<PointCloudSpatialIndex>>>,
+}
+
+impl PointCloudSpatialIndices {
+    /// Returns the index of an asset, building it if needed. Returns `None` if the asset isn't
+    /// loaded or has no positions.
+    pub fn get_or_build(
+        &mut self,
+        id: impl Into<AssetId<PointCloudAsset>>,
+        assets: &Assets<PointCloudAsset>,
+    ) -> Option<Arc<PointCloudSpatialIndex>> {
+        let id = id.into();
+        if let Some(index) = self.indices.get(&id) {
+            return index.clone();
+        }
+        let asset = assets.get(id)?;
+        let index = PointCloudSpatialIndex::from_asset(asset).map(Arc::new);
+        self.indices.insert(id, index.clone());
+        index
+    }
+
+    pub(crate) fn invalidate_system(
+        mut indices: ResMut<Self>,
+        mut events: EventReader<AssetEvent<PointCloudAsset>>,
+    ) {
+        for event in events.read() {
+            match event {
+                AssetEvent::Modified { id } | AssetEvent::Removed { id } => {
+                    indices.indices.remove(id);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A hit returned by [`PointCloudSpatialQuery::ray_cast`] or [`PointCloudSpatialQuery::nearest_point`].
+#[derive(Clone, Debug)]
+pub struct PointCloudRayHit {
+    pub point_cloud: Entity,
+    pub point_index: u32,
+    /// The world space position of the point.
+    pub position: Vec3,
+    /// The distance along the ray to the point's sphere, or to the queried point.
+    pub distance: f32,
+}
+
+/// Spatial queries against every [`PotreePointCloud`] in the world, without a GPU.
+#[derive(SystemParam)]
+pub struct PointCloudSpatialQuery<'w, 's> {
+    indices: ResMut<'w, PointCloudSpatialIndices>,
+    assets: Res<'w, Assets<PointCloudAsset>>,
+    point_clouds: Query<'w, 's, (Entity, &'static PotreePointCloud, &'static GlobalTransform)>,
+}
+
+impl<'w, 's> PointCloudSpatialQuery<'w, 's> {
+    /// Returns the spatial index and transform of a point cloud entity.
+    pub fn index(
+        &mut self,
+        point_cloud: Entity,
+    ) -> Option<(Arc<PointCloudSpatialIndex>, GlobalTransform)> {
+        let (_, point_cloud, transform) = self.point_clouds.get(point_cloud).ok()?;
+        let index = self.indices.get_or_build(&point_cloud.mesh, &self.assets)?;
+        Some((index, *transform))
+    }
+
+    /// Returns the nearest point hit by the ray across all point clouds, treating points as
+    /// spheres of `point_radius`.
+    pub fn ray_cast(
+        &mut self,
+        ray: Ray,
+        max_distance: f32,
+        point_radius: f32,
+    ) -> Option<PointCloudRayHit> {
+        let mut best: Option<PointCloudRayHit> = None;
+        for (entity, point_cloud, transform) in self.point_clouds.iter() {
+            let Some(index) = self.indices.get_or_build(&point_cloud.mesh, &self.assets) else {
+                continue;
+            };
+            let max_distance = best.as_ref().map_or(max_distance, |hit| hit.distance);
+            if let Some((point_index, position, distance)) =
+                index.ray_cast(transform, ray, max_distance, point_radius)
+            {
+                best = Some(PointCloudRayHit {
+                    point_cloud: entity,
+                    point_index,
+                    position,
+                    distance,
+                });
+            }
+        }
+        best
+    }
+
+    /// Returns the point nearest to the world space `point` across all point clouds, if it is
+    /// within `max_distance`.
+    pub fn nearest_point(&mut self, point: Vec3, max_distance: f32) -> Option<PointCloudRayHit> {
+        let mut best: Option<PointCloudRayHit> = None;
+        for (entity, point_cloud, transform) in self.point_clouds.iter() {
+            let Some(index) = self.indices.get_or_build(&point_cloud.mesh, &self.assets) else {
+                continue;
+            };
+            let max_distance = best.as_ref().map_or(max_distance, |hit| hit.distance);
+            if let Some((point_index, position, distance)) =
+                index.nearest_point(transform, point, max_distance)
+            {
+                best = Some(PointCloudRayHit {
+                    point_cloud: entity,
+                    point_index,
+                    position,
+                    distance,
+                });
+            }
+        }
+        best
+    }
+
+    /// Returns the point cloud entity, point index and world space position of the points of
+    /// all point clouds inside the world space axis aligned box from `min` to `max`.
+    pub fn within_box(&mut self, min: Vec3, max: Vec3) -> Vec<(Entity, u32, Vec3)> {
+        let mut found = Vec::new();
+        for (entity, point_cloud, transform) in self.point_clouds.iter() {
+            let Some(index) = self.indices.get_or_build(&point_cloud.mesh, &self.assets) else {
+                continue;
+            };
+            found.extend(
+                index
+                    .within_box(transform, min, max)
+                    .into_iter()
+                    .map(|(point_index, position)| (entity, point_index, position)),
+            );
+        }
+        found
+    }
+
+    /// Returns the `k` points of a point cloud nearest to the world space `point`, with their
+    /// distances.
+    pub fn nearest(&mut self, point_cloud: Entity, point: Vec3, k: usize) -> Vec<(u32, f32)> {
+        self.index(point_cloud)
+            .map(|(index, transform)| index.nearest(&transform, point, k))
+            .unwrap_or_default()
+    }
+
+    /// Returns the points of a point cloud within `radius` of the world space `point`, with
+    /// their distances.
+    pub fn within_radius(
+        &mut self,
+        point_cloud: Entity,
+        point: Vec3,
+        radius: f32,
+    ) -> Vec<(u32, f32)> {
+        self.index(point_cloud)
+            .map(|(index, transform)| index.within_radius(&transform, point, radius))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A deterministic cloud of `len` points in a 10 unit cube.
+    fn random_points(len: usize, mut seed: u64) -> Vec<Vec3> {
+        let mut random = move || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 40) as f32 / (1u64 << 24) as f32 * 10.0
+        };
+        (0..len)
+            .map(|_| Vec3::new(random(), random(), random()))
+            .collect()
+    }
+
+    fn transform() -> GlobalTransform {
+        Transform::from_xyz(1.0, 2.0, 3.0)
+            .with_rotation(Quat::from_rotation_y(0.7))
+            .with_scale(Vec3::new(1.0, 2.0, 0.5))
+            .into()
+    }
+
+    /// The indices and world space distances of all points to `point`, nearest first.
+    fn brute_force(points: &[Vec3], transform: &GlobalTransform, point: Vec3) -> Vec<(u32, f32)> {
+        let mut distances: Vec<(u32, f32)> = points
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| (i as u32, transform.transform_point(p).distance(point)))
+            .collect();
+        distances.sort_by(|a, b| a.1.total_cmp(&b.1));
+        distances
+    }
+
+    fn sorted_indices(found: &[(u32, f32)]) -> Vec<u32> {
+        let mut indices: Vec<u32> = found.iter().map(|&(i, _)| i).collect();
+        indices.sort();
+        indices
+    }
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        let points = random_points(2000, 1);
+        let index = PointCloudSpatialIndex::new(points.iter().copied());
+        let transform = transform();
+        for query in random_points(50, 2) {
+            let expected = brute_force(&points, &transform, query);
+            let found = index.nearest(&transform, query, 8);
+            assert_eq!(found, expected[..8]);
+
+            let (nearest, position, distance) = index
+                .nearest_point(&transform, query, f32::INFINITY)
+                .unwrap();
+            assert_eq!((nearest, distance), expected[0]);
+            assert_eq!(
+                position,
+                transform.transform_point(points[nearest as usize])
+            );
+        }
+    }
+
+    #[test]
+    fn nearest_point_respects_max_distance() {
+        let points = random_points(500, 3);
+        let index = PointCloudSpatialIndex::new(points.iter().copied());
+        let transform = transform();
+        for query in random_points(50, 4) {
+            let expected = brute_force(&points, &transform, query)[0];
+            let max_distance = 0.5;
+            let found = index.nearest_point(&transform, query, max_distance);
+            assert_eq!(
+                found.map(|(i, _, d)| (i, d)),
+                (expected.1 <= max_distance).then_some(expected)
+            );
+        }
+    }
+
+    #[test]
+    fn within_radius_matches_brute_force() {
+        let points = random_points(2000, 5);
+        let index = PointCloudSpatialIndex::new(points.iter().copied());
+        let transform = transform();
+        for query in random_points(50, 6) {
+            let expected: Vec<(u32, f32)> = brute_force(&points, &transform, query)
+                .into_iter()
+                .filter(|&(_, d)| d <= 1.5)
+                .collect();
+            let found = index.within_radius(&transform, query, 1.5);
+            assert_eq!(sorted_indices(&found), sorted_indices(&expected));
+        }
+    }
+
+    #[test]
+    fn within_box_matches_brute_force() {
+        let points = random_points(2000, 11);
+        let index = PointCloudSpatialIndex::new(points.iter().copied());
+        let transform = transform();
+        for (corner, size) in random_points(50, 12).into_iter().zip(random_points(50, 13)) {
+            // Unbounded vertically, like the footprint of a measurement.
+            let min = Vec3::new(corner.x, f32::NEG_INFINITY, corner.z);
+            let max = Vec3::new(
+                corner.x + size.x * 0.3,
+                f32::INFINITY,
+                corner.z + size.z * 0.3,
+            );
+            let expected: Vec<u32> = points
+                .iter()
+                .enumerate()
+                .filter(|(_, &p)| {
+                    let world = transform.transform_point(p);
+                    world.cmpge(min).all() && world.cmple(max).all()
+                })
+                .map(|(i, _)| i as u32)
+                .collect();
+            let found = index.within_box(&transform, min, max);
+            let mut found_indices: Vec<u32> = found.iter().map(|&(i, _)| i).collect();
+            found_indices.sort();
+            assert_eq!(found_indices, expected);
+        }
+    }
+
+    #[test]
+    fn ray_cast_matches_brute_force() {
+        let points = random_points(2000, 7);
+        let index = PointCloudSpatialIndex::new(points.iter().copied());
+        let transform = transform();
+        let radius = 0.1;
+        for (query, direction) in random_points(50, 8).into_iter().zip(random_points(50, 9)) {
+            let ray = Ray {
+                origin: query - Vec3::X * 30.0,
+                direction: Vec3::new(10.0, direction.y - 5.0, direction.z - 5.0),
+            };
+            let normalized = ray.direction.normalize();
+            let expected = points
+                .iter()
+                .enumerate()
+                .filter_map(|(i, &p)| {
+                    ray_sphere(ray.origin, normalized, transform.transform_point(p), radius)
+                        .map(|t| (i as u32, t))
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+            let found = index.ray_cast(&transform, ray, 100.0, radius);
+            assert_eq!(found.map(|(i, _, t)| (i, t)), expected);
+        }
+    }
+
+    #[test]
+    fn empty_cloud() {
+        let index = PointCloudSpatialIndex::new(std::iter::empty());
+        let transform = GlobalTransform::IDENTITY;
+        assert!(index.is_empty());
+        assert!(index.nearest(&transform, Vec3::ZERO, 4).is_empty());
+        assert!(index
+            .nearest_point(&transform, Vec3::ZERO, f32::INFINITY)
+            .is_none());
+        assert!(index.within_radius(&transform, Vec3::ZERO, 10.0).is_empty());
+        assert!(index
+            .within_box(&transform, Vec3::NEG_INFINITY, Vec3::INFINITY)
+            .is_empty());
+        let ray = Ray {
+            origin: Vec3::ZERO,
+            direction: Vec3::X,
+        };
+        assert!(index.ray_cast(&transform, ray, 100.0, 1.0).is_none());
+    }
+
+    #[test]
+    fn k_larger_than_point_count() {
+        let points = random_points(20, 10);
+        let index = PointCloudSpatialIndex::new(points.iter().copied());
+        let transform = transform();
+        let query = Vec3::splat(5.0);
+        let found = index.nearest(&transform, query, 100);
+        assert_eq!(found, brute_force(&points, &transform, query));
+        assert!(index.nearest(&transform, query, 0).is_empty());
+    }
+
+    #[test]
+    fn duplicate_points() {
+        // More copies than fit in a leaf, so they are split across nodes.
+        let mut points = vec![Vec3::ONE; LEAF_SIZE * 3];
+        points.push(Vec3::splat(4.0));
+        let index = PointCloudSpatialIndex::new(points.iter().copied());
+        let transform = GlobalTransform::IDENTITY;
+
+        let found = index.nearest(&transform, Vec3::ONE, LEAF_SIZE * 2);
+        assert_eq!(found.len(), LEAF_SIZE * 2);
+        assert!(found
+            .iter()
+            .all(|&(i, d)| d == 0.0 && i < LEAF_SIZE as u32 * 3));
+        let mut indices = sorted_indices(&found);
+        indices.dedup();
+        assert_eq!(indices.len(), LEAF_SIZE * 2);
+
+        let found = index.within_radius(&transform, Vec3::ONE, 0.5);
+        assert_eq!(
+            sorted_indices(&found),
+            (0..LEAF_SIZE as u32 * 3).collect::<Vec<_>>()
+        );
+
+        let ray = Ray {
+            origin: Vec3::new(-5.0, 1.0, 1.0),
+            direction: Vec3::X,
+        };
+        let (hit, _, t) = index.ray_cast(&transform, ray, 100.0, 0.5).unwrap();
+        assert!(hit < LEAF_SIZE as u32 * 3);
+        assert_eq!(t, 5.5);
+    }
+}
@@ -0,0 +1,21 @@
+@group(0) @binding(0)
+var accumulation_texture: texture_2d<f32>;
+
+@vertex
+fn vertex(
+    @location(0) position: vec2<f32>
+) -> @builtin(position) vec4<f32> {
+    return vec4(position * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// Divides the summed weighted colours by the summed weights, leaving pixels without points.
+@fragment
+fn fragment(
+    @builtin(position) position: vec4<f32>,
+) -> @location(0) vec4<f32> {
+    var sum = textureLoad(accumulation_texture, vec2<i32>(position.xy), 0);
+    if sum.a <= 0.0 {
+        discard;
+    }
+    return vec4<f32>(sum.rgb / sum.a, 1.0);
+}
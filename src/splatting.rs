@@ -0,0 +1,266 @@
+use bevy::{
+    core_pipeline::core_3d::CORE_3D_DEPTH_FORMAT,
+    ecs::query::QueryItem,
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        extract_component::ExtractComponent,
+        render_resource::*,
+        renderer::RenderDevice,
+        texture::{BevyDefault, TextureCache},
+    },
+    utils::HashMap,
+};
+
+use crate::pipeline::POINT_CLOUD_SPLAT_SHADER_HANDLE;
+
+/// How a camera renders point clouds.
+#[derive(Clone, Copy, Component, Debug, Default, PartialEq)]
+pub enum PointCloudRenderMode {
+    /// Each pixel shows the nearest point, which aliases where points overlap.
+    #[default]
+    Standard,
+    /// Blends the overlapping points near the surface, like Potree's high quality splatting:
+    /// a depth pre-pass finds the nearest surface in a separate depth texture, the points
+    /// within `depth_epsilon` behind it are accumulated weighted by their distance to the
+    /// point centre, and another pass normalizes the accumulated colours. The depth of the
+    /// nearest points is then written for eye dome lighting and the passes that follow.
+    /// Costs about three times as much as [`PointCloudRenderMode::Standard`].
+    HighQualitySplatting {
+        /// How far behind the nearest point others are blended with it, in point sizes.
+        /// Compiled into the depth pre-pass, so each value specializes its own pipelines.
+        depth_epsilon: f32,
+    },
+}
+
+impl PointCloudRenderMode {
+    pub fn high_quality_splatting() -> Self {
+        Self::HighQualitySplatting { depth_epsilon: 1.0 }
+    }
+}
+
+impl ExtractComponent for PointCloudRenderMode {
+    type Query = &'static Self;
+    type Filter = With<Camera>;
+    type Out = Self;
+
+    fn extract_component(item: QueryItem<'_, Self::Query>) -> Option<Self> {
+        Some(*item)
+    }
+}
+
+/// The pass of [`PointCloudRenderMode::HighQualitySplatting`] a point cloud pipeline draws.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum SplatPass {
+    /// Draws the nearest points directly.
+    #[default]
+    None,
+    /// Only writes the depth of the nearest points, pushed back by the depth epsilon, into
+    /// [`SplatViewTarget::depth_view`].
+    Depth,
+    /// Adds the weighted colours of the points in front of the pushed back depth.
+    Accumulate,
+    /// Only writes the depth of the nearest points into the view depth and the eye dome
+    /// depth, after their colours were blended.
+    Surface,
+}
+
+/// Premultiplied colours and their weights, summed by [`SplatPass::Accumulate`].
+pub(crate) const SPLAT_ACCUMULATION_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+/// Divides the accumulated colours by their weights.
+#[derive(Resource)]
+pub struct SplatNormalizePipeline {
+    pub accumulation_layout: BindGroupLayout,
+}
+
+#[derive(PartialEq, Eq, Hash, Clone)]
+pub struct SplatNormalizePipelineKey {
+    pub msaa: u32,
+}
+
+impl FromWorld for SplatNormalizePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let accumulation_layout =
+            render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+                label: Some("SplatAccumulationLayout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                }],
+            });
+        Self {
+            accumulation_layout,
+        }
+    }
+}
+
+impl SpecializedRenderPipeline for SplatNormalizePipeline {
+    type Key = SplatNormalizePipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        RenderPipelineDescriptor {
+            label: Some("SplatNormalizePipeline".into()),
+            layout: vec![self.accumulation_layout.clone()],
+            vertex: VertexState {
+                shader: POINT_CLOUD_SPLAT_SHADER_HANDLE,
+                shader_defs: default(),
+                entry_point: "vertex".into(),
+                buffers: vec![VertexBufferLayout {
+                    array_stride: 8,
+                    step_mode: VertexStepMode::Vertex,
+                    attributes: vec![VertexAttribute {
+                        format: VertexFormat::Float32x2,
+                        offset: 0,
+                        shader_location: 0,
+                    }],
+                }],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleStrip,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: None,
+                unclipped_depth: false,
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: key.msaa,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            fragment: Some(FragmentState {
+                shader: POINT_CLOUD_SPLAT_SHADER_HANDLE,
+                shader_defs: default(),
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: TextureFormat::bevy_default(),
+                    blend: Some(BlendState::REPLACE),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            push_constant_ranges: default(),
+        }
+    }
+}
+
+/// The accumulation textures of a camera using
+/// [`PointCloudRenderMode::HighQualitySplatting`].
+#[derive(Clone, Component)]
+pub struct SplatViewTarget {
+    /// The depth of the nearest points pushed back by the depth epsilon, which starts as a
+    /// copy of the view depth so the rest of the scene still hides points.
+    pub depth_texture: Texture,
+    pub depth_view: TextureView,
+    /// Multisampled when MSAA is enabled, resolved into [`Self::resolve_view`].
+    pub accumulation_view: TextureView,
+    pub resolve_view: Option<TextureView>,
+    pub bind_group: BindGroup,
+    pub pipeline_id: CachedRenderPipelineId,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn queue_splat_view_targets(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    mut texture_cache: ResMut<TextureCache>,
+    pipeline_cache: Res<PipelineCache>,
+    normalize_pipeline: Res<SplatNormalizePipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<SplatNormalizePipeline>>,
+    mut cameras: Query<(
+        Entity,
+        &ExtractedCamera,
+        &PointCloudRenderMode,
+        Option<&mut Camera3d>,
+    )>,
+    msaa: Option<Res<Msaa>>,
+) {
+    let msaa = msaa.map(|a| a.samples()).unwrap_or(1);
+    let mut textures = HashMap::default();
+
+    for (entity, camera, render_mode, camera_3d) in cameras.iter_mut() {
+        let PointCloudRenderMode::HighQualitySplatting { .. } = *render_mode else {
+            continue;
+        };
+        let Some(target_size) = camera.physical_target_size else {
+            continue;
+        };
+        // Lets the view depth be copied into the splat depth, the view depth textures are
+        // prepared after this.
+        if let Some(mut camera_3d) = camera_3d {
+            let usage = TextureUsages::from(camera_3d.depth_texture_usages);
+            camera_3d.depth_texture_usages = (usage | TextureUsages::COPY_SRC).into();
+        }
+        let mut texture = |label, sample_count, format, usage| {
+            texture_cache.get(
+                &render_device,
+                TextureDescriptor {
+                    label: Some(label),
+                    size: Extent3d {
+                        width: target_size.x,
+                        height: target_size.y,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count,
+                    dimension: TextureDimension::D2,
+                    format,
+                    usage,
+                    view_formats: &[],
+                },
+            )
+        };
+        let (depth_texture, accumulation_view, resolve_view) = textures
+            .entry(camera.target.clone())
+            .or_insert_with(|| {
+                let accumulation_usage =
+                    TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING;
+                let mut accumulation_texture = |sample_count| {
+                    texture(
+                        "splat_accumulation_texture",
+                        sample_count,
+                        SPLAT_ACCUMULATION_FORMAT,
+                        accumulation_usage,
+                    )
+                    .default_view
+                };
+                let accumulation_view = accumulation_texture(msaa);
+                let resolve_view = (msaa > 1).then(|| accumulation_texture(1));
+                let depth_texture = texture(
+                    "splat_depth_texture",
+                    msaa,
+                    CORE_3D_DEPTH_FORMAT,
+                    TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_DST,
+                );
+                (depth_texture, accumulation_view, resolve_view)
+            })
+            .clone();
+
+        let bind_group = render_device.create_bind_group(
+            "splat_accumulation_bind_group",
+            &normalize_pipeline.accumulation_layout,
+            &BindGroupEntries::single(resolve_view.as_ref().unwrap_or(&accumulation_view)),
+        );
+        commands.entity(entity).insert(SplatViewTarget {
+            depth_texture: depth_texture.texture,
+            depth_view: depth_texture.default_view,
+            accumulation_view,
+            resolve_view,
+            bind_group,
+            pipeline_id: pipelines.specialize(
+                &pipeline_cache,
+                &normalize_pipeline,
+                SplatNormalizePipelineKey { msaa },
+            ),
+        });
+    }
+}
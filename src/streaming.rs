@@ -0,0 +1,277 @@
+use std::ops::Range;
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_resource::{BindGroup, BindGroupEntries, Buffer, BufferDescriptor, BufferUsages},
+        renderer::{RenderDevice, RenderQueue},
+        Extract,
+    },
+    utils::HashMap,
+};
+
+use crate::{clippling_planes::ClippingRanges, PointCloudPipeline, PointCloudUniform};
+
+/// The floats stored for each point: position, colour and the time it was pushed.
+const FLOATS_PER_POINT: usize = 7;
+
+/// A point cloud fed from a live source, like a LiDAR sensor, drawn like a
+/// [`PotreePointCloud`](crate::PotreePointCloud).
+///
+/// Points are kept in a ring buffer of a fixed capacity, so pushing points beyond the capacity
+/// overwrites the oldest ones. Only the pushed points are uploaded to the GPU.
+#[derive(Clone, Component)]
+pub struct StreamingPointCloud {
+    pub point_size: f32,
+    /// Points older than this many seconds are removed.
+    pub max_age: Option<f32>,
+    /// Points shrink to nothing during the last seconds of their [`Self::max_age`].
+    pub fade_duration: f32,
+    /// Position, colour and time of each point of the ring buffer.
+    data: Vec<f32>,
+    capacity: u32,
+    /// The slot of the next pushed point.
+    head: u32,
+    len: u32,
+    /// The number of points pushed since the point cloud was created.
+    pushed: u64,
+    /// The time of [`Time::elapsed_seconds`] at the start of the frame.
+    now: f32,
+}
+
+impl StreamingPointCloud {
+    pub fn new(capacity: u32, point_size: f32) -> Self {
+        Self {
+            point_size,
+            max_age: None,
+            fade_duration: 0.0,
+            data: vec![0.0; capacity as usize * FLOATS_PER_POINT],
+            capacity,
+            head: 0,
+            len: 0,
+            pushed: 0,
+            now: 0.0,
+        }
+    }
+
+    /// Removes points older than `max_age` seconds, after shrinking them during `fade_duration`.
+    pub fn with_max_age(mut self, max_age: f32, fade_duration: f32) -> Self {
+        self.max_age = Some(max_age);
+        self.fade_duration = fade_duration;
+        self
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// The number of points that are shown.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds a point, overwriting the oldest point when the ring buffer is full.
+    pub fn push(&mut self, position: Vec3, color: Color) {
+        if self.capacity == 0 {
+            return;
+        }
+        let [r, g, b, _] = color.as_linear_rgba_f32();
+        let slot = self.head as usize * FLOATS_PER_POINT;
+        self.data[slot..slot + FLOATS_PER_POINT]
+            .copy_from_slice(&[position.x, position.y, position.z, r, g, b, self.now]);
+        self.head = (self.head + 1) % self.capacity;
+        self.len = (self.len + 1).min(self.capacity);
+        self.pushed += 1;
+    }
+
+    pub fn extend(&mut self, points: impl IntoIterator<Item = (Vec3, Color)>) {
+        for (position, color) in points {
+            self.push(position, color);
+        }
+    }
+
+    /// Hides every point, without uploading anything.
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// The positions of the shown points, from the oldest to the newest.
+    pub fn positions(&self) -> impl Iterator<Item = Vec3> + '_ {
+        (0..self.len).map(|i| {
+            let slot = (self.first() + i) % self.capacity;
+            let point = &self.data[slot as usize * FLOATS_PER_POINT..];
+            Vec3::new(point[0], point[1], point[2])
+        })
+    }
+
+    /// The slot of the oldest shown point.
+    fn first(&self) -> u32 {
+        (self.head + self.capacity - self.len) % self.capacity.max(1)
+    }
+
+    /// The slots written by the last `count` pushed points, as at most two ranges.
+    fn last_pushed_slots(&self, count: u32) -> [Range<u32>; 2] {
+        let count = count.min(self.capacity);
+        let start = (self.head + self.capacity - count) % self.capacity.max(1);
+        if start + count <= self.capacity {
+            [start..start + count, 0..0]
+        } else {
+            [start..self.capacity, 0..start + count - self.capacity]
+        }
+    }
+
+    /// Updates the time points are pushed at, and removes the points that are too old.
+    pub fn expire_system(time: Res<Time>, mut point_clouds: Query<&mut StreamingPointCloud>) {
+        let now = time.elapsed_seconds();
+        for mut point_cloud in point_clouds.iter_mut() {
+            point_cloud.now = now;
+            let Some(max_age) = point_cloud.max_age else {
+                continue;
+            };
+            while point_cloud.len > 0 {
+                let oldest = point_cloud.first() as usize * FLOATS_PER_POINT;
+                if now - point_cloud.data[oldest + 6] <= max_age {
+                    break;
+                }
+                point_cloud.len -= 1;
+            }
+        }
+    }
+}
+
+/// The number of points of a [`StreamingPointCloud`] to draw, in the render world.
+#[derive(Component)]
+pub struct ExtractedStreamingPointCloud {
+    pub num_points: u32,
+}
+
+/// The GPU ring buffer of a [`StreamingPointCloud`].
+pub struct StreamingPointCloudBuffer {
+    capacity: u32,
+    buffer: Option<Buffer>,
+    pub bind_group: Option<BindGroup>,
+    /// The number of pushed points that were extracted, `None` until the whole ring buffer
+    /// was extracted once.
+    extracted: Option<u64>,
+    /// Slots and point data to upload.
+    pending: Vec<(u32, Vec<f32>)>,
+}
+
+/// The ring buffers of every [`StreamingPointCloud`], which persist across frames.
+#[derive(Resource, Default)]
+pub struct StreamingPointCloudBuffers(pub HashMap<Entity, StreamingPointCloudBuffer>);
+
+pub(crate) fn extract_streaming_point_clouds(
+    mut commands: Commands,
+    point_clouds: Extract<Query<(Entity, Ref<StreamingPointCloud>, &GlobalTransform)>>,
+    clipping_ranges: Res<ClippingRanges>,
+    mut buffers: ResMut<StreamingPointCloudBuffers>,
+) {
+    buffers.0.retain(|entity, _| point_clouds.contains(*entity));
+
+    let mut values = Vec::new();
+    for (entity, point_cloud, transform) in point_clouds.iter() {
+        let buffer = buffers
+            .0
+            .entry(entity)
+            .or_insert_with(|| StreamingPointCloudBuffer {
+                capacity: point_cloud.capacity,
+                buffer: None,
+                bind_group: None,
+                extracted: None,
+                pending: Vec::new(),
+            });
+        // A new component, possibly with another capacity, is uploaded again.
+        if point_cloud.is_added() {
+            buffer.capacity = point_cloud.capacity;
+            buffer.buffer = None;
+            buffer.bind_group = None;
+            buffer.extracted = None;
+            buffer.pending.clear();
+        }
+        // Only copy the points pushed since the last extraction.
+        let new_points = match buffer.extracted {
+            Some(extracted) => (point_cloud.pushed - extracted).min(u64::from(u32::MAX)) as u32,
+            None => point_cloud.capacity,
+        };
+        for slots in point_cloud.last_pushed_slots(new_points) {
+            if !slots.is_empty() {
+                let data = &point_cloud.data[slots.start as usize * FLOATS_PER_POINT
+                    ..slots.end as usize * FLOATS_PER_POINT];
+                buffer.pending.push((slots.start, data.to_vec()));
+            }
+        }
+        buffer.extracted = Some(point_cloud.pushed);
+
+        let planes = clipping_ranges
+            .planes
+            .get(&entity)
+            .copied()
+            .unwrap_or_default();
+        let volumes = clipping_ranges
+            .volumes
+            .get(&entity)
+            .copied()
+            .unwrap_or_default();
+        values.push((
+            entity,
+            (
+                PointCloudUniform {
+                    transform: transform.compute_matrix(),
+                    point_size: point_cloud.point_size,
+                    first_clipping_plane: planes.first,
+                    num_clipping_planes: planes.count,
+                    first_clipping_volume: volumes.first,
+                    num_clipping_volumes: volumes.count,
+                    stream_time: point_cloud.now,
+                    stream_max_age: point_cloud.max_age.unwrap_or(f32::INFINITY),
+                    stream_fade_duration: point_cloud.fade_duration,
+                    stream_first: point_cloud.first(),
+                    stream_capacity: point_cloud.capacity,
+                    ..default()
+                },
+                ExtractedStreamingPointCloud {
+                    num_points: point_cloud.len,
+                },
+            ),
+        ));
+    }
+    commands.insert_or_spawn_batch(values);
+}
+
+pub(crate) fn prepare_streaming_point_clouds(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    pipeline: Res<PointCloudPipeline>,
+    mut buffers: ResMut<StreamingPointCloudBuffers>,
+) {
+    for buffer in buffers.0.values_mut() {
+        if buffer.capacity == 0 {
+            continue;
+        }
+        let gpu_buffer = buffer.buffer.get_or_insert_with(|| {
+            render_device.create_buffer(&BufferDescriptor {
+                label: Some("Streaming point cloud buffer"),
+                size: (buffer.capacity as usize * FLOATS_PER_POINT * std::mem::size_of::<f32>())
+                    as u64,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+        for (slot, data) in buffer.pending.drain(..) {
+            let offset = slot as usize * FLOATS_PER_POINT * std::mem::size_of::<f32>();
+            render_queue.write_buffer(gpu_buffer, offset as u64, bytemuck::cast_slice(&data));
+        }
+        if buffer.bind_group.is_none() {
+            buffer.bind_group = Some(render_device.create_bind_group(
+                "streaming point cloud bind group",
+                &pipeline.entity_layout,
+                &BindGroupEntries::single(gpu_buffer.as_entire_binding()),
+            ));
+        }
+    }
+}